@@ -0,0 +1,179 @@
+//! The argument list: the files given on the command line or to `:args`
+
+use std::fs;
+use std::path::Path;
+
+#[derive(Default)]
+pub struct ArgList {
+    files: Vec<String>,
+    /// The entry `:next` and `:previous` move from
+    index: usize,
+}
+
+impl ArgList {
+    pub fn new(files: Vec<String>) -> Self {
+        Self { files, index: 0 }
+    }
+
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
+
+    /// Goes `count` entries forward, or back for a negative `count`,
+    /// returning the file to edit
+    pub fn go(&mut self, count: isize) -> Result<&str, String> {
+        let target = self.index as isize + count;
+        if target < 0 {
+            return Err("E164: Cannot go before first file".to_string());
+        }
+        if target as usize >= self.files.len() {
+            return Err("E165: Cannot go beyond last file".to_string());
+        }
+        self.index = target as usize;
+        Ok(&self.files[self.index])
+    }
+
+    /// Goes to entry `index`, returning its file
+    pub fn go_to(&mut self, index: usize) -> Result<&str, String> {
+        if index >= self.files.len() {
+            return Err("E163: There is only one file to edit".to_string());
+        }
+        self.index = index;
+        Ok(&self.files[index])
+    }
+
+    /// What `:args` shows: every file, the current one in brackets
+    pub fn list(&self) -> String {
+        let files = self.files.iter().enumerate().map(|(index, file)| {
+            if index == self.index {
+                format!("[{file}]")
+            } else {
+                file.clone()
+            }
+        });
+        files.collect::<Vec<_>>().join(" ")
+    }
+}
+
+/// The files matching each of the whitespace-separated `patterns`, where
+/// `*`, `?` and `[...]` match within a name and `**` any number of
+/// directories. A pattern matching nothing is kept as it is, for a new
+/// file.
+pub fn expand(patterns: &str) -> Vec<String> {
+    let mut files = Vec::new();
+    for pattern in patterns.split_whitespace() {
+        if !is_wild(pattern) {
+            files.push(pattern.to_string());
+            continue;
+        }
+        let (root, rest) = match pattern.strip_prefix('/') {
+            Some(rest) => ("/", rest),
+            None => ("", pattern),
+        };
+        let parts: Vec<&str> = rest.split('/').filter(|part| !part.is_empty()).collect();
+        let mut found = Vec::new();
+        glob(root, &parts, &mut found);
+        if found.is_empty() {
+            files.push(pattern.to_string());
+        } else {
+            found.sort();
+            files.extend(found);
+        }
+    }
+    files
+}
+
+fn is_wild(pattern: &str) -> bool {
+    pattern.contains(['*', '?', '['])
+}
+
+/// Adds to `found` the paths below `directory`, empty or ending in `/`,
+/// that match the remaining `parts` of a pattern
+fn glob(directory: &str, parts: &[&str], found: &mut Vec<String>) {
+    let Some((part, rest)) = parts.split_first() else {
+        return;
+    };
+    if *part == "**" {
+        glob(directory, rest, found);
+        for (name, is_directory) in entries(directory) {
+            if is_directory && !name.starts_with('.') {
+                glob(&format!("{directory}{name}/"), parts, found);
+            }
+        }
+        return;
+    }
+    if !is_wild(part) {
+        let path = format!("{directory}{part}");
+        if rest.is_empty() {
+            if Path::new(&path).exists() {
+                found.push(path);
+            }
+        } else {
+            glob(&format!("{path}/"), rest, found);
+        }
+        return;
+    }
+    let pattern: Vec<char> = part.chars().collect();
+    for (name, is_directory) in entries(directory) {
+        if name.starts_with('.') && !part.starts_with('.') {
+            continue;
+        }
+        if !matches(&pattern, &name.chars().collect::<Vec<_>>()) {
+            continue;
+        }
+        if rest.is_empty() {
+            found.push(format!("{directory}{name}"));
+        } else if is_directory {
+            glob(&format!("{directory}{name}/"), rest, found);
+        }
+    }
+}
+
+/// The names in `directory` and whether each is a directory, symbolic
+/// links not being followed
+fn entries(directory: &str) -> Vec<(String, bool)> {
+    let Ok(entries) = fs::read_dir(if directory.is_empty() { "." } else { directory }) else {
+        return Vec::new();
+    };
+    entries
+        .flatten()
+        .filter_map(|entry| {
+            let is_directory = entry.file_type().is_ok_and(|kind| kind.is_dir());
+            Some((entry.file_name().into_string().ok()?, is_directory))
+        })
+        .collect()
+}
+
+/// Whether `name` matches `pattern`, where `*` stands for any text, `?`
+/// for any character and `[...]` for one of a set, `[!...]` or `[^...]`
+/// for any other
+fn matches(pattern: &[char], name: &[char]) -> bool {
+    match pattern.split_first() {
+        None => name.is_empty(),
+        Some(('*', rest)) => (0..=name.len()).any(|skip| matches(rest, &name[skip..])),
+        Some(('?', rest)) => !name.is_empty() && matches(rest, &name[1..]),
+        Some(('[', rest)) if rest.iter().skip(1).any(|c| *c == ']') => {
+            let close = 1 + rest.iter().skip(1).position(|c| *c == ']').unwrap();
+            let (negated, set) = match rest[0] {
+                '!' | '^' => (true, &rest[1..close]),
+                _ => (false, &rest[..close]),
+            };
+            let Some((c, name_rest)) = name.split_first() else {
+                return false;
+            };
+            let mut inside = false;
+            let mut index = 0;
+            while index < set.len() {
+                if index + 2 < set.len() && set[index + 1] == '-' {
+                    inside |= (set[index]..=set[index + 2]).contains(c);
+                    index += 3;
+                } else {
+                    inside |= set[index] == *c;
+                    index += 1;
+                }
+            }
+            inside != negated && matches(&rest[close + 1..], name_rest)
+        }
+        Some((c, rest)) => name.first() == Some(c) && matches(rest, &name[1..]),
+    }
+}
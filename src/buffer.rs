@@ -1,10 +1,29 @@
-use crossterm::{cursor, terminal::size, ExecutableCommand, QueueableCommand};
+use crossterm::style::{Attribute, Color, Print, ResetColor, SetAttribute, SetBackgroundColor, SetForegroundColor};
+use crossterm::{cursor, ExecutableCommand, QueueableCommand};
 use std::{
     convert::TryInto,
-    io::{stdout, BufRead, Write},
+    io::{stdout, Write},
 };
-use unicode_segmentation::UnicodeSegmentation;
+
+use crate::regex::Regex;
+use crate::register::{Register, RegisterKind};
+use crate::window::Area;
+use gutter::{Gutter, Sign};
+use search::Match;
+use marks::Marks;
+use visual::Selection;
+use wrap::{Segment, Wrap};
+pub mod gutter;
+pub mod increment;
+mod lines;
+pub mod marks;
 mod row;
+pub mod search;
+pub mod sort;
+pub mod substitute;
+pub mod visual;
+pub mod wrap;
+pub use row::is_keyword;
 use row::Row;
 
 pub type Error = Box<dyn std::error::Error>;
@@ -53,10 +72,70 @@ impl Cursor {
     }
 }
 
+/// How a motion's target relates to the text an operator acts upon
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MotionKind {
+    Exclusive,
+    Inclusive,
+    Linewise,
+}
+
+/// What `draw_rows` should highlight besides the text itself
+#[derive(Default)]
+pub struct Highlights<'a> {
+    /// Every match of the last search pattern, for 'hlsearch'
+    pub search: Option<&'a Regex>,
+    /// The match previewed while typing a pattern, for 'incsearch'
+    pub current: Option<Match>,
+    /// The Visual mode selection
+    pub visual: Option<Selection>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Style {
+    Search,
+    Current,
+    Visual,
+}
+
+impl Style {
+    fn queue(self, stdout: &mut std::io::Stdout) -> Result<()> {
+        match self {
+            Style::Search => {
+                stdout.queue(SetBackgroundColor(Color::Yellow))?;
+                stdout.queue(SetForegroundColor(Color::Black))?;
+            }
+            Style::Current => {
+                stdout.queue(SetAttribute(Attribute::Reverse))?;
+            }
+            Style::Visual => {
+                stdout.queue(SetBackgroundColor(Color::DarkGrey))?;
+            }
+        }
+        Ok(())
+    }
+}
+
 pub struct Buffer {
     contents: Vec<Row>,
     offset: usize,
+    /// The first screen column shown, as the view scrolls sideways
+    left: usize,
     cursor: Cursor,
+    marks: Marks,
+    /// Set by every change until the buffer is written
+    modified: bool,
+    /// Cleared by `:bdelete`, which keeps the buffer out of `:ls`
+    listed: bool,
+    /// Set when the file can't be written, so that `:w` needs a `!`
+    readonly: bool,
+    /// Whether the lines end in CR LF, as the file's did
+    dos: bool,
+    /// The signs placed, in order
+    signs: Vec<Sign>,
+    /// No line above this one is marked for `:global`, so the search for
+    /// the next one starts here
+    marked_from: usize,
 }
 
 impl Buffer {
@@ -65,43 +144,546 @@ impl Buffer {
         if buf.is_empty() {
             contents.push(Row::empty());
         } else {
-            for line in buf.lines() {
-                let row = Row::new(line.unwrap());
-                contents.push(row);
+            for line in String::from_utf8_lossy(buf).lines() {
+                contents.push(Row::new(line));
             }
         }
         Self {
             contents,
             offset: 0,
+            left: 0,
             cursor: Cursor {
                 previous_x: None,
                 x: 0,
                 y: 0,
             },
+            marks: Marks::default(),
+            modified: false,
+            listed: true,
+            readonly: false,
+            signs: Vec::new(),
+            marked_from: 0,
+            dos: buf.iter().position(|byte| *byte == b'\n').is_some_and(|end| end > 0 && buf[end - 1] == b'\r'),
         }
     }
 
-    pub fn draw_rows(&mut self, screen_rows: usize) -> Result<()> {
+    pub fn is_modified(&self) -> bool {
+        self.modified
+    }
+
+    /// Forgets the changes were not written, once they are
+    pub fn set_saved(&mut self) {
+        self.modified = false;
+    }
+
+    pub fn is_listed(&self) -> bool {
+        self.listed
+    }
+
+    pub fn set_listed(&mut self, listed: bool) {
+        self.listed = listed;
+    }
+
+    pub fn is_readonly(&self) -> bool {
+        self.readonly
+    }
+
+    pub fn set_readonly(&mut self, readonly: bool) {
+        self.readonly = readonly;
+    }
+
+    /// `dos` or `unix`, how the lines end
+    pub fn file_format(&self) -> &'static str {
+        if self.dos {
+            "dos"
+        } else {
+            "unix"
+        }
+    }
+
+    /// How each line ends when written
+    pub fn line_ending(&self) -> &'static str {
+        if self.dos {
+            "\r\n"
+        } else {
+            "\n"
+        }
+    }
+
+    /// Draws the buffer from the scroll offset into `area` and puts the
+    /// cursor there
+    pub fn draw_rows(&self, area: Area, (gutter, wrap): (&Gutter, Option<&Wrap>), highlights: &Highlights) -> Result<()> {
+        let line = self.cursor_position().0;
+        self.draw_lines(area, (self.offset, self.left), line, (gutter, wrap), highlights)?;
+        let width = area.width.saturating_sub(self.gutter_width(gutter));
+        let (row, column) = self.wrapped_screen_position(width, wrap);
         let mut stdout = stdout();
-        stdout.queue(cursor::SavePosition)?;
-        stdout.queue(cursor::MoveTo(0, 0))?;
-        let mut contents_iter = self.contents.iter().skip(self.offset);
-        let mut count = 0;
-        while count != screen_rows {
-            if let Some(line) = contents_iter.next() {
-                stdout.queue(crossterm::style::Print(&line.render))?;
-                stdout.queue(cursor::MoveToNextLine(1))?;
+        stdout.queue(cursor::MoveTo(
+            (area.left + self.gutter_width(gutter) + column).try_into()?,
+            (area.top + row).try_into()?,
+        ))?;
+        stdout.flush()?;
+        Ok(())
+    }
+
+    /// Draws the lines from `top` into `area`, after the gutter for a
+    /// cursor on `cursor_line`, with `~` past the end of the buffer. Lines
+    /// either wrap, a last one that doesn't fit showing as `@`, or are cut
+    /// to the width of the area from screen column `left` on.
+    pub fn draw_lines(
+        &self,
+        area: Area,
+        (top, left): (usize, usize),
+        cursor_line: usize,
+        (gutter, wrap): (&Gutter, Option<&Wrap>),
+        highlights: &Highlights,
+    ) -> Result<()> {
+        let mut stdout = stdout();
+        let styles = self.styles(top, area.height, highlights);
+        let width = area.width.saturating_sub(self.gutter_width(gutter));
+        let left = if wrap.is_some() { 0 } else { left };
+        let mut row = 0;
+        for (line, styles) in (top..).zip(&styles) {
+            if row == area.height {
+                break;
+            }
+            let past_end = line >= self.contents.len();
+            let segments = match past_end {
+                true => vec![Segment::default()],
+                false => self.segments(line, width, wrap),
+            };
+            let fits = row + segments.len() <= area.height || row == 0;
+            for (index, segment) in segments.iter().enumerate() {
+                if row == area.height {
+                    break;
+                }
+                stdout.queue(cursor::MoveTo(area.left.try_into()?, (area.top + row).try_into()?))?;
+                row += 1;
+                let first = index == 0 && fits;
+                let shown = (!past_end && fits).then_some(line);
+                let (sign, number) = self.gutter_text(shown.filter(|_| first), cursor_line, gutter);
+                let gutter: String = format!("{sign}{number}").chars().take(area.width).collect();
+                stdout.queue(Print(&gutter[..sign.len().min(gutter.len())]))?;
+                stdout.queue(SetForegroundColor(Color::DarkYellow))?;
+                stdout.queue(Print(&gutter[sign.len().min(gutter.len())..]))?;
+                stdout.queue(ResetColor)?;
+                let Some(line) = shown else {
+                    let filler = if past_end { "~" } else { "@" };
+                    stdout.queue(SetForegroundColor(Color::Blue))?;
+                    stdout.queue(Print(format!("{filler:<width$}")))?;
+                    stdout.queue(ResetColor)?;
+                    continue;
+                };
+                let row_text = &self.contents[line];
+                let (indent, showbreak) = wrap.map(|wrap| wrap.prefix_text(segment.prefix)).unwrap_or_default();
+                stdout.queue(Print(indent))?;
+                stdout.queue(SetForegroundColor(Color::Blue))?;
+                stdout.queue(Print(showbreak))?;
+                stdout.queue(ResetColor)?;
+                let from = row_text.visual_distance(0, segment.start);
+                let columns = match wrap {
+                    Some(_) => (from, from + row_text.visual_distance(segment.start, segment.end)),
+                    None => (left, left + width),
+                };
+                Self::draw_row(&mut stdout, row_text, styles, columns, width - segment.prefix)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The style of every grapheme on screen, row by row
+    fn styles(&self, top: usize, screen_rows: usize, highlights: &Highlights) -> Vec<Vec<Option<Style>>> {
+        let visible = top..(top + screen_rows).min(self.contents.len());
+        let mut styles: Vec<Vec<Option<Style>>> = (0..screen_rows)
+            .map(|row| {
+                let length = self.contents.get(top + row).map_or(0, Row::len);
+                vec![None; length]
+            })
+            .collect();
+        let mut paint = |found: &Match, style: Style| {
+            for line in found.start.0.max(visible.start)..=found.end.0.min(visible.end.saturating_sub(1)) {
+                let row = &mut styles[line - top];
+                let from = if line == found.start.0 { found.start.1 } else { 0 };
+                let to = if line == found.end.0 { found.end.1 } else { row.len() };
+                for style_of in row.iter_mut().take(to).skip(from) {
+                    *style_of = Some(style);
+                }
+            }
+        };
+        if let Some(regex) = highlights.search {
+            for line in visible.start.saturating_sub(1)..visible.end {
+                for found in self.line_matches(regex, line) {
+                    paint(&found, Style::Search);
+                }
+            }
+        }
+        if let Some(current) = &highlights.current {
+            paint(current, Style::Current);
+        }
+        if let Some(selection) = &highlights.visual {
+            for line in visible {
+                if let Some((from, to, _)) = self.selected_graphemes(selection, line) {
+                    for style_of in styles[line - top].iter_mut().take(to).skip(from) {
+                        *style_of = Some(Style::Visual);
+                    }
+                }
+            }
+        }
+        styles
+    }
+
+    /// Draws the screen columns `left..right` of `line`, padded to `width`.
+    /// A wide character cut by the left edge shows as `<`, by the right one
+    /// as `>`.
+    fn draw_row(
+        stdout: &mut std::io::Stdout,
+        line: &Row,
+        styles: &[Option<Style>],
+        (left, right): (usize, usize),
+        width: usize,
+    ) -> Result<()> {
+        let room = width.min(right - left);
+        let mut run = String::new();
+        let mut run_style = None;
+        let mut used = 0;
+        let mut end = 0;
+        let graphemes = line.render_graphemes().into_iter().zip(line.widths());
+        for ((grapheme, length), style) in graphemes.zip(styles) {
+            let start = end;
+            end += length;
+            if end <= left {
+                continue;
+            }
+            let shown = (end - start.max(left)).min(room - used);
+            let cut = |mark: &str| if grapheme.trim().is_empty() { " ".repeat(shown) } else { mark.repeat(shown) };
+            let text = if start < left {
+                cut("<")
+            } else if shown < *length {
+                cut(">")
             } else {
-                stdout.queue(crossterm::style::Print('~'))?;
-                stdout.queue(cursor::MoveToNextLine(1))?;
+                grapheme.to_string()
+            };
+            used += shown;
+            if *style != run_style {
+                Self::draw_run(stdout, &run, run_style)?;
+                run.clear();
+                run_style = *style;
+            }
+            run.push_str(&text);
+            if used == room {
+                break;
             }
-            count += 1;
         }
-        stdout.queue(cursor::RestorePosition)?;
-        stdout.flush()?;
+        Self::draw_run(stdout, &run, run_style)?;
+        stdout.queue(Print(" ".repeat(width - used)))?;
+        Ok(())
+    }
+
+    fn draw_run(stdout: &mut std::io::Stdout, run: &str, style: Option<Style>) -> Result<()> {
+        if run.is_empty() {
+            return Ok(());
+        }
+        match style {
+            Some(style) => {
+                style.queue(stdout)?;
+                stdout.queue(Print(run))?;
+                stdout.queue(SetAttribute(Attribute::Reset))?;
+                stdout.queue(ResetColor)?;
+            }
+            None => {
+                stdout.queue(Print(run))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Moves the terminal cursor to where `Cursor` says it is
+    pub fn place_cursor(&self) -> Result<()> {
+        let (row, column) = self.screen_position();
+        stdout().queue(cursor::MoveTo(column.try_into().unwrap(), row.try_into().unwrap()))?;
         Ok(())
     }
 
+    /// Where the cursor is on the screen, as (row, column) from the top left
+    /// of the window
+    pub fn screen_position(&self) -> (usize, usize) {
+        let (start, _) = self.cursor_columns(self.cursor_position());
+        (self.cursor.y, start.saturating_sub(self.left))
+    }
+
+    /// The screen columns a cursor at `(line, x)` covers, from the start of
+    /// the line, a cursor past its end covering one
+    fn cursor_columns(&self, (line, x): (usize, usize)) -> (usize, usize) {
+        let row = &self.contents[line];
+        let x = x.min(row.len());
+        let start = row.visual_distance(0, x);
+        (start, start + row.widths().get(x).copied().unwrap_or(1))
+    }
+
+    pub fn left_column(&self) -> usize {
+        self.left
+    }
+
+    pub fn set_left_column(&mut self, left: usize) {
+        self.left = left;
+    }
+
+    /// The first column to show in a window `width` columns wide for the
+    /// cursor at `cursor` to be in view, `sidescrolloff` columns away from
+    /// either edge, when `left` was shown first. The view moves by at least
+    /// `sidescroll` columns, or so as to center the cursor for 0 or when it
+    /// is far out of view.
+    pub fn visible_left(
+        &self,
+        cursor: (usize, usize),
+        left: usize,
+        width: usize,
+        (sidescroll, sidescrolloff): (usize, usize),
+    ) -> usize {
+        let (start, end) = self.cursor_columns(cursor);
+        let margin = sidescrolloff.min(width.saturating_sub(1) / 2);
+        let lowest = (end + margin).saturating_sub(width);
+        let highest = start.saturating_sub(margin);
+        if (lowest..=highest).contains(&left) {
+            return left;
+        }
+        let distance = if left < lowest { lowest - left } else { left - highest };
+        if sidescroll == 0 || distance >= width / 2 {
+            return start.saturating_sub(width / 2).clamp(lowest, highest.max(lowest));
+        }
+        if left < lowest {
+            (left + sidescroll).clamp(lowest, highest.max(lowest))
+        } else {
+            left.saturating_sub(sidescroll).clamp(lowest, highest.max(lowest))
+        }
+    }
+
+    /// Scrolls sideways for the cursor to be in view in a window `width`
+    /// columns wide, as `visible_left` says
+    pub fn scroll_sideways(&mut self, width: usize, sidescroll: (usize, usize)) {
+        self.left = self.visible_left(self.cursor_position(), self.left, width, sidescroll);
+    }
+
+    /// `zl` and `zh`: scrolls the view `delta` columns right, or left for a
+    /// negative `delta`, no further than the end of the cursor line, the
+    /// cursor moving along when it would go out of view
+    pub fn scroll_columns(&mut self, delta: isize, width: usize, sidescrolloff: usize) {
+        let (line, x) = self.cursor_position();
+        let row = &self.contents[line];
+        let last = row.visual_distance(0, row.len()).saturating_sub(1);
+        self.left = self.left.saturating_add_signed(delta).min(last);
+        let margin = sidescrolloff.min(width.saturating_sub(1) / 2);
+        let (start, end) = self.cursor_columns((line, x));
+        let column = if start < self.left + margin {
+            self.left + margin
+        } else if end + margin > self.left + width {
+            (self.left + width).saturating_sub(margin + 1)
+        } else {
+            return;
+        };
+        let mut x = row.grapheme_at_column(column);
+        if row.visual_distance(0, x) < self.left {
+            x = (x + 1).min(row.len().saturating_sub(1));
+        }
+        self.set_cursor_position((line, x));
+    }
+
+    /// `zs` and `ze`: scrolls the view for the cursor to be at its start,
+    /// or at its end, `sidescrolloff` columns away from the edge
+    pub fn scroll_cursor_to_edge(&mut self, start: bool, width: usize, sidescrolloff: usize) {
+        let (first, end) = self.cursor_columns(self.cursor_position());
+        let margin = sidescrolloff.min(width.saturating_sub(1) / 2);
+        self.left = if start {
+            first.saturating_sub(margin)
+        } else {
+            (end + margin).saturating_sub(width)
+        };
+    }
+
+    /// The byte, from 1 and 0 on an empty line, and the screen column, from
+    /// 1, of `(line, x)`
+    pub fn columns(&self, (line, x): (usize, usize)) -> (usize, usize) {
+        let row = &self.contents[line];
+        if row.is_empty() {
+            return (0, 1);
+        }
+        let x = x.min(row.len() - 1);
+        let width = row.visual_distance(x, x + 1);
+        (row.byte_idx_of(x) + 1, row.visual_distance(0, x) + width)
+    }
+
+    /// Position of the cursor as (line, grapheme) in the buffer
+    pub fn cursor_position(&self) -> (usize, usize) {
+        (self.cursor.y + self.offset, self.cursor.x)
+    }
+
+    pub fn line_count(&self) -> usize {
+        self.contents.len()
+    }
+
+    /// Cursor position and scroll offset, to come back to after a preview
+    pub fn view(&self) -> ((usize, usize), usize) {
+        (self.cursor_position(), self.offset)
+    }
+
+    pub fn restore_view(&mut self, (position, offset): ((usize, usize), usize)) {
+        self.offset = offset;
+        self.set_cursor_position(position);
+    }
+
+    /// Places the cursor at the given (line, grapheme), scrolling if needed
+    pub fn move_to(&mut self, (line, x): (usize, usize), screen_rows: usize) -> Result<()> {
+        let line = line.min(self.contents.len() - 1);
+        if line < self.offset {
+            self.offset = line;
+        } else if line >= self.offset + screen_rows {
+            self.offset = line + 1 - screen_rows;
+        }
+        self.cursor.y = line - self.offset;
+        self.cursor.x = x.min(self.current_line().len().saturating_sub(1));
+        self.cursor.previous_x = None;
+        self.place_cursor()
+    }
+
+    /// Like `move_to`, but the cursor may go just past the end of the line,
+    /// where Insert mode appends
+    pub fn move_to_insert(&mut self, (line, x): (usize, usize), screen_rows: usize) -> Result<()> {
+        self.move_to((line, x), screen_rows)?;
+        self.cursor.x = x.min(self.current_line().len());
+        self.place_cursor()
+    }
+
+    /// Index of the first non-blank grapheme of the given line
+    pub fn first_char_of(&self, line: usize) -> usize {
+        let row = &self.contents[line];
+        let byte = row
+            .raw
+            .char_indices()
+            .find(|(_, c)| !c.is_whitespace())
+            .map_or(0, |(index, _)| index);
+        row.grapheme_idx_of(byte)
+    }
+
+    /// The keyword under or after the cursor, as used by `*` and `#`
+    pub fn word_under_cursor(&self) -> Option<String> {
+        let line = self.current_line();
+        let cursor = line.byte_idx_of(self.cursor.x.min(line.len()));
+        let candidates: Vec<_> = line
+            .words()
+            .filter(|(start, word)| start + word.len() > cursor)
+            .collect();
+        candidates
+            .iter()
+            .find(|(_, word)| word.starts_with(is_keyword))
+            .or_else(|| candidates.first())
+            .map(|(_, word)| word.to_string())
+    }
+
+    /// The bracket matching the first of `()[]{}` at or after `position` on
+    /// its line, as `%` finds it
+    pub fn matching_bracket(&self, (line, x): (usize, usize)) -> Option<(usize, usize)> {
+        const PAIRS: [(char, char); 3] = [('(', ')'), ('[', ']'), ('{', '}')];
+        let row = &self.contents[line];
+        let start = row.byte_idx_of(x.min(row.len()));
+        let (offset, bracket) = row.raw[start..]
+            .char_indices()
+            .find(|(_, c)| PAIRS.iter().any(|(open, close)| c == open || c == close))?;
+        let (open, close) = *PAIRS.iter().find(|(open, close)| bracket == *open || bracket == *close)?;
+        let start = start + offset;
+        let mut depth = 0;
+        let mut found = |line: usize, index: usize, c: char| {
+            if c == bracket {
+                depth += 1;
+            } else if c == open || c == close {
+                depth -= 1;
+            }
+            (depth == 0).then(|| (line, self.contents[line].grapheme_idx_of(index)))
+        };
+        if bracket == open {
+            (line..self.contents.len()).find_map(|at| {
+                let from = if at == line { start } else { 0 };
+                let raw = &self.contents[at].raw;
+                raw[from..].char_indices().find_map(|(index, c)| found(at, from + index, c))
+            })
+        } else {
+            (0..=line).rev().find_map(|at| {
+                let raw = &self.contents[at].raw;
+                let to = if at == line { start + bracket.len_utf8() } else { raw.len() };
+                raw[..to].char_indices().rev().find_map(|(index, c)| found(at, index, c))
+            })
+        }
+    }
+
+    /// The text between two positions as an operator would see it
+    pub fn text_range(&self, mut from: (usize, usize), mut to: (usize, usize), kind: MotionKind) -> Register {
+        if to < from {
+            std::mem::swap(&mut from, &mut to);
+        }
+        if kind == MotionKind::Linewise {
+            let last = to.0.min(self.contents.len() - 1);
+            return Register::linewise(self.contents[from.0..=last].iter().map(|row| row.raw.clone()).collect());
+        }
+        if kind == MotionKind::Inclusive {
+            to.1 += 1;
+        }
+        let byte = |(line, x): (usize, usize)| {
+            let row = &self.contents[line];
+            row.byte_idx_of(x.min(row.len()))
+        };
+        let (start, end) = ((from.0, byte(from)), (to.0, byte(to)));
+        let text = crate::regex::slice(self.contents.as_slice(), start, end);
+        Register {
+            lines: text.split('\n').map(str::to_string).collect(),
+            kind: RegisterKind::Charwise,
+        }
+    }
+
+    /// Deletes the text between two positions as an operator would
+    pub fn delete_range(
+        &mut self,
+        mut from: (usize, usize),
+        mut to: (usize, usize),
+        kind: MotionKind,
+    ) -> Result<()> {
+        if to < from {
+            std::mem::swap(&mut from, &mut to);
+        }
+        match kind {
+            MotionKind::Linewise => {
+                self.delete_lines(crate::ex::Range {
+                    start: from.0,
+                    end: to.0,
+                });
+            }
+            MotionKind::Exclusive | MotionKind::Inclusive => {
+                if kind == MotionKind::Inclusive {
+                    to.1 += 1;
+                }
+                let first = &self.contents[from.0];
+                let head = first.raw[..first.byte_idx_of(from.1.min(first.len()))].to_string();
+                let last = &self.contents[to.0];
+                let tail = &last.raw[last.byte_idx_of(to.1.min(last.len()))..];
+                let joined = Row::new(head + tail);
+                self.contents.splice(from.0..=to.0, std::iter::once(joined));
+                self.lines_replaced(from.0, to.0 - from.0 + 1, 1);
+                self.changed(from, from);
+                self.set_cursor_position(from);
+            }
+        }
+        Ok(())
+    }
+
+    /// Updates the cursor without touching the terminal or the scroll offset,
+    /// unless the line has gone out of view
+    fn set_cursor_position(&mut self, (line, x): (usize, usize)) {
+        if line < self.offset {
+            self.offset = line;
+        }
+        self.cursor.y = line - self.offset;
+        self.cursor.x = x;
+        self.cursor.previous_x = None;
+    }
+
     pub fn move_down(&mut self, count: u16, screen_lines: usize) -> Result<()> {
         if self.cursor.y + self.offset >= self.contents.len().saturating_sub(1) {
             return Ok(());
@@ -124,7 +706,7 @@ impl Buffer {
             return Ok(());
         }
         let mut stdout = stdout();
-        if self.cursor.y.checked_sub(count.into()) != None {
+        if self.cursor.y.checked_sub(count.into()).is_some() {
             stdout.execute(cursor::MoveUp(count))?;
             self.cursor.y -= usize::from(count);
         } else {
@@ -175,7 +757,7 @@ impl Buffer {
             self.cursor.previous_x = None;
         }
         let line = self.current_line();
-        let mut final_position = self.cursor.x.saturating_sub(usize::from(count));
+        let final_position = self.cursor.x.saturating_sub(usize::from(count));
         let distance = line.visual_distance(final_position, self.cursor.x);
         stdout().execute(cursor::MoveLeft(distance.try_into().unwrap()))?;
         self.cursor.x = final_position;
@@ -201,50 +783,124 @@ impl Buffer {
         Ok(())
     }
 
-    pub fn insert_char(&mut self, character: char) -> Result<()> {
-        let line = self.contents.get_mut(self.cursor.y + self.offset).unwrap();
-        line.insert_char(self.cursor.x, character);
-        if character == '\t' {
-            self.move_right(1)?;
-        } else {
-            self.move_right_forced(1)?;
+    /// Replaces `count` graphemes of a line from `position` with `text`,
+    /// returning the text replaced
+    pub fn overwrite(&mut self, (line, x): (usize, usize), count: usize, text: &str) -> String {
+        let row = &mut self.contents[line];
+        let x = x.min(row.len());
+        let (from, to) = (row.byte_idx_of(x), row.byte_idx_of((x + count).min(row.len())));
+        let mut raw = std::mem::take(&mut row.raw);
+        let replaced = raw[from..to].to_string();
+        raw.replace_range(from..to, text);
+        row.set_raw(raw);
+        self.changed((line, x), (line, x));
+        replaced
+    }
+
+    /// Inserts `text`, which may hold line breaks, at `position`, returning
+    /// the position just after it
+    pub fn insert_text(&mut self, (line, x): (usize, usize), text: &str) -> (usize, usize) {
+        let row = &self.contents[line];
+        let at = row.byte_idx_of(x.min(row.len()));
+        let joined = format!("{}{text}{}", &row.raw[..at], &row.raw[at..]);
+        let rows: Vec<Row> = joined.split('\n').map(Row::new).collect();
+        let added = rows.len();
+        let end_byte = match text.rfind('\n') {
+            Some(index) => text.len() - index - 1,
+            None => at + text.len(),
+        };
+        let end = (line + added - 1, rows[added - 1].grapheme_idx_of(end_byte));
+        self.contents.splice(line..=line, rows);
+        self.lines_replaced(line, 1, added);
+        self.changed((line, x), end);
+        end
+    }
+
+    /// Where the word before `position` starts on its line, as `Ctrl-W`
+    /// deletes back to in Insert mode
+    pub fn word_start_before(&self, (line, x): (usize, usize)) -> usize {
+        let graphemes = self.contents[line].raw_graphemes();
+        let class = |grapheme: &str| match grapheme.chars().next() {
+            Some(c) if c.is_whitespace() => 0,
+            Some(c) if is_keyword(c) => 1,
+            _ => 2,
+        };
+        let mut start = x.min(graphemes.len());
+        while start > 0 && class(graphemes[start - 1]) == 0 {
+            start -= 1;
         }
-        Ok(())
+        if let Some(word) = start.checked_sub(1).map(|before| class(graphemes[before])) {
+            while start > 0 && class(graphemes[start - 1]) == word {
+                start -= 1;
+            }
+        }
+        start
+    }
+
+    /// Breaks a line in two at `position`, leaving the cursor at the start
+    /// of the second one
+    pub fn split_line(&mut self, (line, x): (usize, usize)) {
+        let row = &mut self.contents[line];
+        let at = row.byte_idx_of(x.min(row.len()));
+        let mut raw = std::mem::take(&mut row.raw);
+        let tail = raw.split_off(at);
+        row.set_raw(raw);
+        self.contents.insert(line + 1, Row::new(tail));
+        self.lines_replaced(line + 1, 0, 1);
+        self.changed((line, x), (line + 1, 0));
+        self.set_cursor_position((line + 1, 0));
     }
 
     pub fn remove_char(&mut self) -> Result<()> {
-        let line = self.contents.get_mut(self.cursor.y + self.offset).unwrap();
+        let position = self.cursor_position();
+        let line = self.contents.get_mut(position.0).unwrap();
         if line.is_empty() {
             return Ok(());
         }
         line.remove_char(self.cursor.x)?;
-        if self.cursor.x >= line.len() {
+        let length = line.len();
+        self.changed(position, position);
+        if self.cursor.x >= length {
             self.move_left(1)?;
         }
         Ok(())
     }
 
+    /// Deletes the grapheme before the cursor, joining the line with the
+    /// previous one when the cursor is at its start
     pub fn remove_char_before_cursor(&mut self) -> Result<()> {
+        let line = self.cursor.y + self.offset;
         if self.cursor.x == 0 {
+            if line > 0 {
+                let column = self.contents[line - 1].len();
+                self.join_lines(crate::ex::Range { start: line - 1, end: line }, false);
+                self.set_cursor_position((line - 1, column));
+            }
             return Ok(());
         }
         self.move_left(1)?;
-        let line = self.contents.get_mut(self.cursor.y + self.offset).unwrap();
+        let position = self.cursor_position();
+        let line = self.contents.get_mut(position.0).unwrap();
         line.remove_char(self.cursor.x)?;
+        self.changed(position, position);
         Ok(())
     }
 
-    pub fn new_line_after_cursor(&mut self) -> Result<()> {
-        let (_, mut rows) = size()?;
-        rows -= 1;
-        self.contents.insert(self.cursor.y + self.offset + 1, Row::empty());
-        self.move_down(1, rows.into())?;
+    pub fn new_line_after_cursor(&mut self, screen_rows: usize) -> Result<()> {
+        let line = self.cursor.y + self.offset + 1;
+        self.contents.insert(line, Row::empty());
+        self.lines_replaced(line, 0, 1);
+        self.changed((line, 0), (line, 0));
+        self.move_down(1, screen_rows)?;
         Ok(())
     }
 
     pub fn new_line_before_cursor(&mut self) -> Result<()> {
         self.move_start_of_line()?;
-        self.contents.insert(self.cursor.y + self.offset, Row::empty());
+        let line = self.cursor.y + self.offset;
+        self.contents.insert(line, Row::empty());
+        self.lines_replaced(line, 0, 1);
+        self.changed((line, 0), (line, 0));
         Ok(())
     }
     //
@@ -0,0 +1,130 @@
+//! The columns left of the text: the sign column and the line numbers
+
+use unicode_segmentation::UnicodeSegmentation;
+
+use super::row::width;
+use super::Buffer;
+
+/// What the gutter of a window shows, as the options say
+#[derive(Clone, Copy, Default)]
+pub struct Gutter {
+    /// `number`: the number of every line
+    pub number: bool,
+    /// `relativenumber`: how far every line is from the cursor line, which
+    /// shows its own number when `number` is set too
+    pub relative: bool,
+    /// `numberwidth`: the least room for a number and the space after it
+    pub number_width: usize,
+    /// `signcolumn`: `Some(true)` for always, `Some(false)` for never and
+    /// `None` for when the buffer has signs
+    pub signs: Option<bool>,
+}
+
+/// How wide the sign column is, and so the text of a sign at most
+pub const SIGN_WIDTH: usize = 2;
+
+/// How many screen columns the text of a sign takes
+pub fn sign_width(text: &str) -> usize {
+    text.graphemes(true).map(width).sum()
+}
+
+/// A marker in the sign column, placed by id and kept on its text as lines
+/// are inserted or deleted above it
+pub struct Sign {
+    pub id: usize,
+    /// The name it was defined with, for listing
+    pub name: String,
+    pub text: String,
+    /// The anchor keeping its line
+    anchor: usize,
+}
+
+impl Buffer {
+    /// Puts sign `id` on `line`, moving it there if it was placed already
+    pub fn place_sign(&mut self, id: usize, line: usize, name: &str, text: &str) {
+        self.unplace_sign(id);
+        let anchor = self.add_anchor((line, 0));
+        self.signs.push(Sign {
+            id,
+            name: name.to_string(),
+            text: text.to_string(),
+            anchor,
+        });
+    }
+
+    /// Takes sign `id` away, returning whether it was placed
+    pub fn unplace_sign(&mut self, id: usize) -> bool {
+        let Some(index) = self.signs.iter().position(|sign| sign.id == id) else {
+            return false;
+        };
+        let sign = self.signs.remove(index);
+        self.remove_anchor(sign.anchor);
+        true
+    }
+
+    pub fn unplace_signs(&mut self) {
+        for sign in std::mem::take(&mut self.signs) {
+            self.remove_anchor(sign.anchor);
+        }
+    }
+
+    /// Every sign placed, with its line, from the top of the buffer
+    pub fn signs(&self) -> Vec<(usize, &Sign)> {
+        let mut signs: Vec<(usize, &Sign)> = self.signs.iter().map(|sign| (self.sign_line(sign), sign)).collect();
+        signs.sort_by_key(|(line, sign)| (*line, sign.id));
+        signs
+    }
+
+    fn sign_line(&self, sign: &Sign) -> usize {
+        self.anchor(sign.anchor).map_or(0, |(line, _)| line)
+    }
+
+    /// The sign shown on `line`, the last one placed there
+    fn sign(&self, line: usize) -> Option<&Sign> {
+        self.signs.iter().rev().find(|sign| self.sign_line(sign) == line)
+    }
+
+    /// How many columns `gutter` takes before the text
+    pub fn gutter_width(&self, gutter: &Gutter) -> usize {
+        let (signs, numbers) = self.gutter_columns(gutter);
+        signs + numbers
+    }
+
+    /// How wide the sign column and the number column are
+    fn gutter_columns(&self, gutter: &Gutter) -> (usize, usize) {
+        let signs = match gutter.signs {
+            Some(shown) => shown,
+            None => !self.signs.is_empty(),
+        };
+        let numbers = if gutter.number || gutter.relative {
+            gutter.number_width.max(self.contents.len().to_string().len() + 1)
+        } else {
+            0
+        };
+        (if signs { SIGN_WIDTH } else { 0 }, numbers)
+    }
+
+    /// The sign column and the number column `gutter` shows for `line`,
+    /// `None` past the end of the buffer, relative numbers counting from
+    /// `cursor_line`
+    pub(super) fn gutter_text(&self, line: Option<usize>, cursor_line: usize, gutter: &Gutter) -> (String, String) {
+        let (signs, numbers) = self.gutter_columns(gutter);
+        let sign = match line.and_then(|line| self.sign(line)) {
+            Some(sign) if signs > 0 => sign.text.as_str(),
+            _ => "",
+        };
+        let number = match line {
+            Some(line) if numbers > 0 => {
+                let room = numbers - 1;
+                match (gutter.number, gutter.relative) {
+                    (true, true) if line == cursor_line => format!("{:<room$} ", line + 1),
+                    (_, true) => format!("{:>room$} ", line.abs_diff(cursor_line)),
+                    _ => format!("{:>room$} ", line + 1),
+                }
+            }
+            _ => String::new(),
+        };
+        let padding = " ".repeat(signs.saturating_sub(sign_width(sign)));
+        (format!("{sign}{padding}"), format!("{number:<numbers$}"))
+    }
+}
@@ -0,0 +1,117 @@
+use super::Buffer;
+
+/// A number in a line, as byte offsets
+struct Number {
+    /// Where it starts, `-` or `0x`-like prefix included
+    start: usize,
+    /// Where its digits start
+    digits: usize,
+    end: usize,
+    radix: u32,
+}
+
+/// The first number in `text` ending after byte `from`
+fn find_number(text: &str, from: usize) -> Option<Number> {
+    let bytes = text.as_bytes();
+    let mut index = 0;
+    while index < bytes.len() {
+        if !bytes[index].is_ascii_digit() {
+            index += 1;
+            continue;
+        }
+        let number = number_at(bytes, index);
+        if number.end > from {
+            return Some(number);
+        }
+        index = number.end;
+    }
+    None
+}
+
+/// The number whose first digit is at `start`: hexadecimal after `0x`,
+/// binary after `0b`, octal after a leading `0`, else decimal and negative
+/// after `-`
+fn number_at(bytes: &[u8], start: usize) -> Number {
+    let is_digit = |index: usize, radix: u32| bytes.get(index).is_some_and(|b| char::from(*b).is_digit(radix));
+    let run = |mut index: usize, radix: u32| {
+        while is_digit(index, radix) {
+            index += 1;
+        }
+        index
+    };
+    if bytes[start] == b'0' {
+        for (letter, radix) in [(b'x', 16), (b'b', 2)] {
+            let prefix = bytes.get(start + 1).map(u8::to_ascii_lowercase) == Some(letter);
+            if prefix && is_digit(start + 2, radix) {
+                return Number {
+                    start,
+                    digits: start + 2,
+                    end: run(start + 2, radix),
+                    radix,
+                };
+            }
+        }
+        let end = run(start, 10);
+        if end - start > 1 && end == run(start, 8) {
+            return Number {
+                start,
+                digits: start + 1,
+                end,
+                radix: 8,
+            };
+        }
+    }
+    let negative = start > 0 && bytes[start - 1] == b'-';
+    Number {
+        start: start - usize::from(negative),
+        digits: start,
+        end: run(start, 10),
+        radix: 10,
+    }
+}
+
+/// The text of `number` once `delta` is added to it, keeping its prefix,
+/// its width when written with leading zeros and the case of hex digits
+fn add(text: &str, number: &Number, delta: i64) -> String {
+    let digits = &text[number.digits..number.end];
+    let width = if digits.starts_with('0') { digits.len() } else { 0 };
+    if number.radix == 10 {
+        let negative = number.start < number.digits;
+        let value = text[number.start..number.end]
+            .parse::<i64>()
+            .unwrap_or(if negative { i64::MIN } else { i64::MAX })
+            .saturating_add(delta);
+        let sign = if value < 0 { "-" } else { "" };
+        return format!("{sign}{:0width$}", value.unsigned_abs());
+    }
+    let value = u64::from_str_radix(digits, number.radix)
+        .unwrap_or(u64::MAX)
+        .wrapping_add_signed(delta);
+    let prefix = &text[number.start..number.digits];
+    let width = width.max(digits.len());
+    match number.radix {
+        16 if digits.chars().rev().find(char::is_ascii_alphabetic).is_some_and(|c| c.is_ascii_uppercase()) => {
+            format!("{prefix}{value:0width$X}")
+        }
+        16 => format!("{prefix}{value:0width$x}"),
+        2 => format!("{prefix}{value:0width$b}"),
+        _ => format!("{prefix}{value:0width$o}"),
+    }
+}
+
+impl Buffer {
+    /// Adds `delta` to the first number of `line` ending after grapheme `x`
+    /// and starting before grapheme `to`, as `Ctrl-A` and `Ctrl-X` do,
+    /// returning the grapheme it now ends on
+    pub fn increment(&mut self, line: usize, x: usize, to: usize, delta: i64) -> Option<usize> {
+        let row = &self.contents[line];
+        let number = find_number(&row.raw, row.byte_idx_of(x.min(row.len())))?;
+        let (start, end) = (row.grapheme_idx_of(number.start), row.grapheme_idx_of(number.end));
+        if row.grapheme_idx_of(number.digits) >= to {
+            return None;
+        }
+        let replacement = add(&row.raw, &number, delta);
+        self.overwrite((line, start), end - start, &replacement);
+        Some(start + replacement.len() - 1)
+    }
+}
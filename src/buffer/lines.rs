@@ -0,0 +1,165 @@
+use super::{Buffer, Result, Row, TAB_STOP};
+use crate::ex::Range;
+use crate::regex::Regex;
+
+impl Buffer {
+    /// The text of the lines in `range`
+    pub fn lines(&self, range: Range) -> Vec<String> {
+        self.contents[range.start..=range.end]
+            .iter()
+            .map(|row| row.raw.clone())
+            .collect()
+    }
+
+    /// Deletes whole lines, leaving the cursor on the line after them
+    pub fn delete_lines(&mut self, range: Range) {
+        self.contents.drain(range.start..=range.end);
+        self.lines_replaced(range.start, range.end - range.start + 1, 0);
+        if self.contents.is_empty() {
+            self.contents.push(Row::empty());
+        }
+        let line = range.start.min(self.contents.len() - 1);
+        self.changed((line, 0), (line, 0));
+        self.set_cursor_position((line, self.first_char_of(line)));
+    }
+
+    /// Inserts lines below `below`, or above the first line when `None`,
+    /// returning the last line inserted
+    pub fn insert_lines(&mut self, below: Option<usize>, lines: Vec<String>) -> usize {
+        let at = below.map_or(0, |line| line + 1);
+        let count = lines.len();
+        self.contents.splice(at..at, lines.into_iter().map(Row::new));
+        self.lines_replaced(at, 0, count);
+        self.changed((at, 0), (at + count - 1, 0));
+        at + count - 1
+    }
+
+    /// Moves the lines in `range` below `below` (above the first line when
+    /// `None`), returning where the last of them ended up
+    pub fn move_lines(&mut self, range: Range, below: Option<usize>) -> Result<usize> {
+        if below.is_some_and(|line| line >= range.start && line < range.end) {
+            return Err("E134: Cannot move a range of lines into itself".into());
+        }
+        let count = range.end - range.start + 1;
+        let moved: Vec<Row> = self.contents.drain(range.start..=range.end).collect();
+        let at = match below {
+            Some(line) if line >= range.end => line + 1 - count,
+            Some(line) => line + 1,
+            None => 0,
+        };
+        self.contents.splice(at..at, moved);
+        let start = range.start;
+        let remap = |line: usize| {
+            if (start..start + count).contains(&line) {
+                return line - start + at;
+            }
+            let left = if line < start { line } else { line - count };
+            if left < at {
+                left
+            } else {
+                left + count
+            }
+        };
+        self.lines_remapped(remap);
+        // the lines moved and the others each keep their order, so the first
+        // of each that could be marked is the highest it could now be
+        let end = start + count;
+        let from = self.marked_from;
+        let outside = remap(if (start..end).contains(&from) { end } else { from });
+        let inside = (end > from).then(|| remap(start.max(from)));
+        self.marked_from = inside.map_or(outside, |inside| inside.min(outside));
+        self.changed((at, 0), (at + count - 1, 0));
+        Ok(at + count - 1)
+    }
+
+    /// Joins the lines in `range` into one, a single line being joined with
+    /// the next. With `spaces`, leading white space of the joined lines is
+    /// replaced by a space as `J` does. Returns the grapheme where the last
+    /// join happened.
+    pub fn join_lines(&mut self, range: Range, spaces: bool) -> usize {
+        let end = range.end.max(range.start + 1).min(self.contents.len() - 1);
+        let mut joined = self.contents[range.start].raw.clone();
+        let mut column = joined.len();
+        for row in &self.contents[range.start + 1..=end] {
+            column = joined.len();
+            if !spaces {
+                joined.push_str(&row.raw);
+                continue;
+            }
+            let text = row.raw.trim_start();
+            let ends_in_space = joined.ends_with([' ', '\t']);
+            if !joined.is_empty() && !text.is_empty() && !ends_in_space && !text.starts_with(')') {
+                joined.push(' ');
+            }
+            joined.push_str(text);
+        }
+        let row = Row::new(joined);
+        let column = row.grapheme_idx_of(column);
+        self.contents.splice(range.start..=end, std::iter::once(row));
+        self.lines_replaced(range.start, end - range.start + 1, 1);
+        self.changed((range.start, 0), (range.start, column));
+        column
+    }
+
+    /// Shifts the indent of every non-empty line in `range` by `amount`
+    /// times `shiftwidth` columns
+    pub fn shift_lines(&mut self, range: Range, amount: isize, shiftwidth: usize, expandtab: bool) {
+        for row in &mut self.contents[range.start..=range.end] {
+            if row.raw.is_empty() {
+                continue;
+            }
+            let text = row.raw.trim_start_matches([' ', '\t']);
+            let width = indent_width(&row.raw[..row.raw.len() - text.len()]);
+            let width = (width as isize + amount * shiftwidth as isize).max(0) as usize;
+            let marked = row.marked;
+            *row = Row::new(indent(width, expandtab) + text);
+            row.marked = marked;
+        }
+        self.changed((range.start, 0), (range.end, 0));
+    }
+
+    /// Marks the lines in `range` that match `regex` (or that don't, with
+    /// `invert`) for `:global`, returning how many were marked
+    pub fn mark_lines(&mut self, regex: &Regex, range: Range, invert: bool) -> usize {
+        self.marked_from = range.start;
+        let mut count = 0;
+        for line in range.start..=range.end {
+            let marked = self.matches_line(regex, line) != invert;
+            self.contents[line].marked = marked;
+            count += usize::from(marked);
+        }
+        count
+    }
+
+    /// Unmarks and returns the first marked line, wherever earlier commands
+    /// have moved it
+    pub fn take_marked(&mut self) -> Option<usize> {
+        let from = self.marked_from.min(self.contents.len());
+        let line = from + self.contents[from..].iter().position(|row| row.marked)?;
+        self.contents[line].marked = false;
+        self.marked_from = line + 1;
+        Some(line)
+    }
+
+    pub fn clear_marked(&mut self) {
+        for row in &mut self.contents {
+            row.marked = false;
+        }
+    }
+}
+
+/// The number of screen columns taken by leading white space
+fn indent_width(whitespace: &str) -> usize {
+    whitespace.chars().fold(0, |width, c| match c {
+        '\t' => width + TAB_STOP - width % TAB_STOP,
+        _ => width + 1,
+    })
+}
+
+/// White space filling `width` columns, with tabs unless `expandtab`
+fn indent(width: usize, expandtab: bool) -> String {
+    if expandtab {
+        return " ".repeat(width);
+    }
+    "\t".repeat(width / TAB_STOP) + &" ".repeat(width % TAB_STOP)
+}
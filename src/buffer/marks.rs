@@ -0,0 +1,199 @@
+use std::collections::HashMap;
+
+use super::{Buffer, Result};
+
+/// Every mark, in the order `:marks` lists them
+pub const ORDER: &str = "'abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ[]^.<>";
+
+/// How many entries the change list keeps
+const CHANGES: usize = 100;
+
+/// Changes on the same line closer than this many graphemes to the last
+/// one update it rather than adding to the change list
+const CHANGE_DISTANCE: usize = 79;
+
+/// Positions in a buffer as (line, grapheme), kept on the same text when
+/// lines are inserted or deleted above them
+#[derive(Default)]
+pub struct Marks {
+    positions: HashMap<char, (usize, usize)>,
+    /// Where changes were made, oldest first, for `g;` and `g,`
+    changes: Vec<(usize, usize)>,
+    /// The change `g;` and `g,` last went to, `changes.len()` after a
+    /// change
+    change_index: usize,
+    /// Positions the jump list refers to
+    anchors: HashMap<usize, (usize, usize)>,
+    next_anchor: usize,
+}
+
+/// Where a position goes after lines `line..line + removed` were replaced
+/// by `added` lines, `None` when its line was deleted
+fn adjusted((at, column): (usize, usize), line: usize, removed: usize, added: usize) -> Option<(usize, usize)> {
+    if at < line + removed.min(added) {
+        Some((at, column))
+    } else if at < line + removed {
+        None
+    } else {
+        Some((at + added - removed, column))
+    }
+}
+
+impl Marks {
+    pub fn is_valid(name: char) -> bool {
+        ORDER.contains(name) || name == '`'
+    }
+
+    /// `` ` `` is another name for `'`
+    fn key(name: char) -> char {
+        if name == '`' {
+            '\''
+        } else {
+            name
+        }
+    }
+
+    fn get(&self, name: char) -> Option<(usize, usize)> {
+        self.positions.get(&Self::key(name)).copied()
+    }
+
+    fn set(&mut self, name: char, position: (usize, usize)) {
+        self.positions.insert(Self::key(name), position);
+    }
+
+    /// Lines `line..line + removed` were replaced by `added` lines. The
+    /// first lines replaced keep their marks; marks set with `m` go away
+    /// with the lines deleted, while the others move to the line after.
+    fn adjust(&mut self, line: usize, removed: usize, added: usize) {
+        let deleted = (line + added, 0);
+        self.positions.retain(|name, position| match adjusted(*position, line, removed, added) {
+            Some(moved) => {
+                *position = moved;
+                true
+            }
+            None if name.is_ascii_alphabetic() => false,
+            None => {
+                *position = deleted;
+                true
+            }
+        });
+        for position in self.changes.iter_mut().chain(self.anchors.values_mut()) {
+            *position = adjusted(*position, line, removed, added).unwrap_or(deleted);
+        }
+    }
+
+    /// Moves every mark through `f`, which maps old lines to new ones
+    fn remap(&mut self, f: impl Fn(usize) -> usize) {
+        let positions = self.positions.values_mut();
+        for (at, _) in positions.chain(self.changes.iter_mut()).chain(self.anchors.values_mut()) {
+            *at = f(*at);
+        }
+    }
+}
+
+impl Buffer {
+    /// Where mark `name` is, kept within the buffer
+    pub fn mark(&self, name: char) -> Option<(usize, usize)> {
+        self.marks.get(name).map(|position| self.clamped(position))
+    }
+
+    pub fn set_mark(&mut self, name: char, position: (usize, usize)) {
+        self.marks.set(name, position);
+    }
+
+    pub fn remove_mark(&mut self, name: char) {
+        self.marks.positions.remove(&name);
+    }
+
+    /// Keeps `position` around as the text changes, returning an id for
+    /// `anchor`
+    pub fn add_anchor(&mut self, position: (usize, usize)) -> usize {
+        let id = self.marks.next_anchor;
+        self.marks.next_anchor += 1;
+        self.marks.anchors.insert(id, position);
+        id
+    }
+
+    pub fn anchor(&self, id: usize) -> Option<(usize, usize)> {
+        self.marks.anchors.get(&id).map(|position| self.clamped(*position))
+    }
+
+    pub fn remove_anchor(&mut self, id: usize) {
+        self.marks.anchors.remove(&id);
+    }
+
+    /// The change list, oldest first, and the index `g;` and `g,` move from
+    pub fn changes(&self) -> (Vec<(usize, usize)>, usize) {
+        let changes = self.marks.changes.iter().map(|position| self.clamped(*position));
+        (changes.collect(), self.marks.change_index)
+    }
+
+    /// Goes `count` changes back in the change list, or forward for a
+    /// negative `count`, stopping at either end
+    pub fn older_change(&mut self, count: isize) -> Result<(usize, usize)> {
+        let length = self.marks.changes.len();
+        let index = self.marks.change_index;
+        if length == 0 {
+            return Err("E664: Changelist is empty".into());
+        }
+        let target = index as isize - count;
+        let target = if target < 0 {
+            if index == 0 {
+                return Err("E662: At start of changelist".into());
+            }
+            0
+        } else if target as usize >= length {
+            if index + 1 >= length {
+                return Err("E663: At end of changelist".into());
+            }
+            length - 1
+        } else {
+            target as usize
+        };
+        self.marks.change_index = target;
+        Ok(self.clamped(self.marks.changes[target]))
+    }
+
+    /// Keeps the marks on their text after lines `line..line + removed`
+    /// were replaced by `added` lines, and `:global` looking for the next
+    /// marked line where it could be
+    pub(super) fn lines_replaced(&mut self, line: usize, removed: usize, added: usize) {
+        if line + removed <= self.marked_from {
+            self.marked_from = self.marked_from - removed + added;
+        } else if line < self.marked_from {
+            // the new lines may have kept the marks of those they replaced
+            self.marked_from = line;
+        }
+        if removed != added {
+            self.marks.adjust(line, removed, added);
+        }
+    }
+
+    pub(super) fn lines_remapped(&mut self, f: impl Fn(usize) -> usize) {
+        self.marks.remap(f);
+    }
+
+    /// Records a change between two positions for `'[`, `']`, `'.` and the
+    /// change list, the buffer now being modified
+    pub(super) fn changed(&mut self, start: (usize, usize), end: (usize, usize)) {
+        self.modified = true;
+        self.marks.set('[', start);
+        self.marks.set(']', end);
+        self.marks.set('.', start);
+        let changes = &mut self.marks.changes;
+        match changes.last_mut() {
+            Some(last) if last.0 == start.0 && last.1.abs_diff(start.1) < CHANGE_DISTANCE => *last = start,
+            _ => {
+                if changes.len() == CHANGES {
+                    changes.remove(0);
+                }
+                changes.push(start);
+            }
+        }
+        self.marks.change_index = changes.len();
+    }
+
+    fn clamped(&self, (line, column): (usize, usize)) -> (usize, usize) {
+        (line.min(self.contents.len() - 1), column)
+    }
+}
@@ -10,6 +10,8 @@ pub struct Row {
     pub render: String, // where tabs and the like are visually represented
     indices: Vec<usize>,
     positions: Vec<usize>,
+    /// Set on the lines `:global` still has to visit
+    pub marked: bool,
 }
 
 pub type Error = Box<dyn std::error::Error>;
@@ -26,6 +28,7 @@ impl Row {
             render: String::new(),
             indices: Vec::new(),
             positions: Vec::new(),
+            marked: false,
         };
         row.do_render().unwrap();
         row
@@ -46,24 +49,24 @@ impl Row {
     fn do_render(&mut self) -> Result<()> {
         let mut graphemes_count = 0;
         let mut index: usize = 0;
-        let raw_len = self.raw.len();
         self.render.clear();
         self.positions.clear();
         self.positions.reserve(self.raw.len());
         for c in self.raw.graphemes(true) {
             if c == "\t" {
+                let start = index;
                 loop {
                     self.render.push(' ');
                     index += 1;
-                    if index % super::TAB_STOP == 0 {
+                    if index.is_multiple_of(TAB_STOP) {
                         break;
                     }
                 }
-                self.positions.push(TAB_STOP);
+                self.positions.push(index - start);
             } else {
-                index += c.len();
+                index += width(c);
                 self.render.push_str(c);
-                self.positions.push(1);
+                self.positions.push(width(c));
             }
             graphemes_count += 1;
         }
@@ -79,7 +82,7 @@ impl Row {
         Ok(())
     }
 
-    fn byte_idx_of(&self, char: usize) -> usize {
+    pub fn byte_idx_of(&self, char: usize) -> usize {
         let len = self.indices.len();
         if len == 0 {
             char
@@ -90,6 +93,51 @@ impl Row {
         }
     }
 
+    /// Index of the grapheme that contains the given byte
+    pub fn grapheme_idx_of(&self, byte: usize) -> usize {
+        if self.indices.is_empty() {
+            byte.min(self.raw.len())
+        } else {
+            self.indices.partition_point(|index| *index < byte)
+        }
+    }
+
+    pub fn raw_graphemes(&self) -> Vec<&str> {
+        self.raw.graphemes(true).collect()
+    }
+
+    /// The rendered text of every grapheme, tabs already expanded
+    pub fn render_graphemes(&self) -> Vec<&str> {
+        let mut offset = 0;
+        self.raw
+            .graphemes(true)
+            .zip(&self.positions)
+            .map(|(c, width)| {
+                let length = if c == "\t" { *width } else { c.len() };
+                offset += length;
+                &self.render[offset - length..offset]
+            })
+            .collect()
+    }
+
+    /// How many screen columns each grapheme takes
+    pub fn widths(&self) -> &[usize] {
+        &self.positions
+    }
+
+    /// The grapheme covering screen column `column`, the last one if the
+    /// line is shorter
+    pub fn grapheme_at_column(&self, column: usize) -> usize {
+        let mut end = 0;
+        for (index, width) in self.positions.iter().enumerate() {
+            end += width;
+            if end > column {
+                return index;
+            }
+        }
+        self.positions.len().saturating_sub(1)
+    }
+
     pub fn visual_distance(&self, mut from: usize, mut to: usize) -> usize {
         let mut sum: usize = 0;
         if to < from {
@@ -101,15 +149,16 @@ impl Row {
         sum
     }
 
-    // pub fn next_word(&mut self, at: usize, string: String) -> usize {
-    // }
-
-    pub fn insert_char(&mut self, at: usize, c: char) {
-        if at == self.len() {
-            self.raw.push(c);
-        } else {
-            self.raw.insert(self.byte_idx_of(at), c);
+    pub fn words(&self) -> ViWords<'_> {
+        ViWords {
+            iter: &self.raw,
+            position: 0,
         }
+    }
+
+    /// Replaces the text of the row, keeping its mark
+    pub fn set_raw(&mut self, raw: String) {
+        self.raw = raw;
         self.do_render().unwrap()
     }
 
@@ -138,15 +187,105 @@ impl Index<usize> for Row {
     }
 }
 
+/// How many screen columns `grapheme` takes: two for the wide characters
+/// of East Asian scripts and for emoji, one for the others
+pub fn width(grapheme: &str) -> usize {
+    const WIDE: &[(u32, u32)] = &[
+        (0x1100, 0x115F),
+        (0x231A, 0x231B),
+        (0x2329, 0x232A),
+        (0x23E9, 0x23EC),
+        (0x23F0, 0x23F0),
+        (0x23F3, 0x23F3),
+        (0x25FD, 0x25FE),
+        (0x2614, 0x2615),
+        (0x2648, 0x2653),
+        (0x267F, 0x267F),
+        (0x2693, 0x2693),
+        (0x26A1, 0x26A1),
+        (0x26AA, 0x26AB),
+        (0x26BD, 0x26BE),
+        (0x26C4, 0x26C5),
+        (0x26CE, 0x26CE),
+        (0x26D4, 0x26D4),
+        (0x26EA, 0x26EA),
+        (0x26F2, 0x26F5),
+        (0x26FA, 0x26FD),
+        (0x2705, 0x2705),
+        (0x270A, 0x270B),
+        (0x2728, 0x2728),
+        (0x274C, 0x274C),
+        (0x274E, 0x274E),
+        (0x2753, 0x2755),
+        (0x2757, 0x2757),
+        (0x2795, 0x2797),
+        (0x27B0, 0x27B0),
+        (0x27BF, 0x27BF),
+        (0x2B1B, 0x2B1C),
+        (0x2B50, 0x2B50),
+        (0x2B55, 0x2B55),
+        (0x2E80, 0x303E),
+        (0x3041, 0x33FF),
+        (0x3400, 0x4DBF),
+        (0x4E00, 0x9FFF),
+        (0xA000, 0xA4CF),
+        (0xA960, 0xA97F),
+        (0xAC00, 0xD7A3),
+        (0xF900, 0xFAFF),
+        (0xFE10, 0xFE19),
+        (0xFE30, 0xFE6F),
+        (0xFF00, 0xFF60),
+        (0xFFE0, 0xFFE6),
+        (0x1F004, 0x1F004),
+        (0x1F0CF, 0x1F0CF),
+        (0x1F18E, 0x1F18E),
+        (0x1F191, 0x1F19A),
+        (0x1F200, 0x1F251),
+        (0x1F300, 0x1F64F),
+        (0x1F680, 0x1F6FF),
+        (0x1F7E0, 0x1F7EB),
+        (0x1F90C, 0x1F9FF),
+        (0x1FA70, 0x1FAFF),
+        (0x20000, 0x2FFFD),
+        (0x30000, 0x3FFFD),
+    ];
+    let Some(first) = grapheme.chars().next() else {
+        return 0;
+    };
+    let code = u32::from(first);
+    let wide = WIDE.iter().any(|(start, end)| (*start..=*end).contains(&code));
+    // U+FE0F asks for the emoji form of the character before it
+    if wide || grapheme.contains('\u{FE0F}') {
+        2
+    } else {
+        1
+    }
+}
+
+/// Characters that make up a keyword, like Vim's default 'iskeyword'
+pub fn is_keyword(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
 /// Iterator over Vi-style words 
-struct ViWords<'a> {
-    pub iter: &'a str
+pub struct ViWords<'a> {
+    pub iter: &'a str,
+    position: usize,
 }
 
 impl<'a> Iterator for ViWords<'a> {
     type Item = (usize, &'a str);
     fn next(&mut self) -> Option<Self::Item> {
-        todo!()
+        let rest = &self.iter[self.position..];
+        let (skipped, first) = rest.char_indices().find(|(_, c)| !c.is_whitespace())?;
+        let start = self.position + skipped;
+        let keyword = is_keyword(first);
+        let length = self.iter[start..]
+            .char_indices()
+            .find(|(_, c)| c.is_whitespace() || is_keyword(*c) != keyword)
+            .map_or(self.iter.len() - start, |(index, _)| index);
+        self.position = start + length;
+        Some((start, &self.iter[start..self.position]))
     }
 }
 
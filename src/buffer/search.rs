@@ -0,0 +1,201 @@
+use super::{Buffer, MotionKind, Row};
+use crate::regex::{self, Lines, Regex};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Direction {
+    Forward,
+    Backward,
+}
+
+impl Direction {
+    pub fn reverse(self) -> Self {
+        match self {
+            Direction::Forward => Direction::Backward,
+            Direction::Backward => Direction::Forward,
+        }
+    }
+
+    /// The character that starts (and delimits) a search in this direction
+    pub fn prompt(self) -> char {
+        match self {
+            Direction::Forward => '/',
+            Direction::Backward => '?',
+        }
+    }
+}
+
+/// Where the cursor lands relative to a match, e.g. the `e+1` in `/foo/e+1`
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub enum SearchOffset {
+    #[default]
+    None,
+    Line(isize),
+    Start(isize),
+    End(isize),
+}
+
+#[derive(Clone, Debug)]
+pub struct Search {
+    pub pattern: String,
+    pub direction: Direction,
+    pub offset: SearchOffset,
+}
+
+/// A match in the buffer, as (line, grapheme) positions with an exclusive end
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Match {
+    pub start: (usize, usize),
+    pub end: (usize, usize),
+}
+
+impl Search {
+    /// Parses what was typed after `/` or `?`, i.e. a pattern optionally
+    /// followed by the delimiter and an offset
+    pub fn parse(input: &str, direction: Direction) -> Self {
+        let delimiter = direction.prompt();
+        let mut pattern = String::new();
+        let mut offset = None;
+        let mut chars = input.char_indices();
+        while let Some((index, c)) = chars.next() {
+            if c == '\\' {
+                match chars.next() {
+                    Some((_, next)) if next == delimiter => pattern.push(next),
+                    Some((_, next)) => {
+                        pattern.push(c);
+                        pattern.push(next);
+                    }
+                    None => pattern.push(c),
+                }
+            } else if c == delimiter {
+                offset = Some(&input[index + 1..]);
+                break;
+            } else {
+                pattern.push(c);
+            }
+        }
+        Self {
+            pattern,
+            direction,
+            offset: offset.map(parse_offset).unwrap_or_default(),
+        }
+    }
+
+    pub fn regex(&self) -> Result<Regex, regex::Error> {
+        Regex::new(&self.pattern)
+    }
+
+    /// Where the cursor goes for the given match, and how an operator
+    /// should treat that motion
+    pub fn target(&self, buffer: &Buffer, found: &Match) -> ((usize, usize), MotionKind) {
+        match self.offset {
+            SearchOffset::None => (found.start, MotionKind::Exclusive),
+            SearchOffset::Start(count) => (
+                (found.start.0, found.start.1.saturating_add_signed(count)),
+                if count > 0 {
+                    MotionKind::Inclusive
+                } else {
+                    MotionKind::Exclusive
+                },
+            ),
+            SearchOffset::End(count) => (
+                (
+                    found.end.0,
+                    found.end.1.saturating_sub(1).saturating_add_signed(count),
+                ),
+                MotionKind::Inclusive,
+            ),
+            SearchOffset::Line(count) => {
+                let line = found
+                    .start
+                    .0
+                    .saturating_add_signed(count)
+                    .min(buffer.line_count() - 1);
+                ((line, buffer.first_char_of(line)), MotionKind::Linewise)
+            }
+        }
+    }
+}
+
+fn parse_offset(offset: &str) -> SearchOffset {
+    let number = |text: &str| -> isize {
+        match text {
+            "" => 0,
+            "+" => 1,
+            "-" => -1,
+            _ => text.trim_start_matches('+').parse().unwrap_or(0),
+        }
+    };
+    match offset.chars().next() {
+        None => SearchOffset::None,
+        Some('e') => SearchOffset::End(number(&offset[1..])),
+        Some('s' | 'b') => SearchOffset::Start(number(&offset[1..])),
+        Some(_) => SearchOffset::Line(number(offset)),
+    }
+}
+
+impl Lines for [Row] {
+    fn line_count(&self) -> usize {
+        self.len()
+    }
+
+    fn line(&self, index: usize) -> &str {
+        &self[index].raw
+    }
+}
+
+impl Buffer {
+    /// Finds the next match of `regex` after (or before) `from`, wrapping
+    /// around the ends of the buffer. The flag tells if it wrapped.
+    pub fn find(
+        &self,
+        regex: &Regex,
+        from: (usize, usize),
+        direction: Direction,
+    ) -> Option<(Match, bool)> {
+        let total = self.contents.len();
+        for step in 0..=total {
+            let (line, wrapped) = match direction {
+                Direction::Forward => ((from.0 + step) % total, from.0 + step >= total),
+                Direction::Backward => ((from.0 + total - step) % total, step > from.0),
+            };
+            let matches = self.line_matches(regex, line);
+            let found = match direction {
+                Direction::Forward => matches.into_iter().find(|found| {
+                    (step != 0 || found.start.1 > from.1)
+                        && (step != total || found.start.1 <= from.1)
+                }),
+                Direction::Backward => matches.into_iter().rev().find(|found| {
+                    (step != 0 || found.start.1 < from.1)
+                        && (step != total || found.start.1 >= from.1)
+                }),
+            };
+            if let Some(found) = found {
+                return Some((found, wrapped));
+            }
+        }
+        None
+    }
+
+    /// Matches of `regex` that start on `line`, in grapheme positions
+    pub fn line_matches(&self, regex: &Regex, line: usize) -> Vec<Match> {
+        regex
+            .captures_in_line(self.contents.as_slice(), line)
+            .iter()
+            .map(|captures| Match {
+                start: self.grapheme_position(captures.start()),
+                end: self.grapheme_position(captures.end()),
+            })
+            .filter(|found| found.start.0 == line)
+            .collect()
+    }
+
+    /// Whether `regex` matches anywhere in `line`
+    pub fn matches_line(&self, regex: &Regex, line: usize) -> bool {
+        regex.captures_from(self.contents.as_slice(), line, 0).is_some()
+    }
+
+    /// Converts a (line, byte) position into a (line, grapheme) one
+    pub fn grapheme_position(&self, (line, byte): regex::Position) -> (usize, usize) {
+        (line, self.contents[line].grapheme_idx_of(byte))
+    }
+}
@@ -0,0 +1,212 @@
+use std::cmp::Ordering;
+
+use super::{Buffer, Result, Row};
+use crate::ex::Range;
+use crate::regex::Regex;
+
+/// What part of a line `:sort` compares, and how
+#[derive(Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub enum SortKind {
+    #[default]
+    Text,
+    /// `n`: the first decimal number
+    Decimal,
+    /// `x`: the first hexadecimal number, with or without `0x`
+    Hex,
+    /// `f`: the first floating point number
+    Float,
+    /// `b`: the first binary number, with or without `0b`
+    Binary,
+    /// `o`: the first octal number, with or without `0o`
+    Octal,
+}
+
+#[derive(Clone, Default, Debug)]
+pub struct Sort {
+    pub kind: SortKind,
+    /// `!`: the order is reversed
+    pub reverse: bool,
+    /// `i`
+    pub ignore_case: bool,
+    /// `u`: only the first of lines comparing equal is kept
+    pub unique: bool,
+    /// `/pat/`: lines are compared on what comes after the match, an empty
+    /// pattern being the last search pattern
+    pub pattern: Option<String>,
+    /// `r`: lines are compared on the match itself
+    pub use_match: bool,
+}
+
+/// The part of a line `:sort` compares
+enum Key {
+    Text(String),
+    /// Lines without a number sort first
+    Integer(Option<i64>),
+    Float(Option<f64>),
+}
+
+impl Sort {
+    /// Parses what follows `:sort`, e.g. `n u /^\w\+=/`
+    pub fn parse(argument: &str, reverse: bool) -> Result<Self> {
+        let mut sort = Self {
+            reverse,
+            ..Default::default()
+        };
+        let invalid = || format!("E474: Invalid argument: {argument}");
+        let mut rest = argument;
+        while let Some(c) = rest.chars().next() {
+            rest = &rest[c.len_utf8()..];
+            let kind = match c {
+                ' ' | '\t' => continue,
+                'i' => {
+                    sort.ignore_case = true;
+                    continue;
+                }
+                'u' => {
+                    sort.unique = true;
+                    continue;
+                }
+                'r' => {
+                    sort.use_match = true;
+                    continue;
+                }
+                'n' => SortKind::Decimal,
+                'x' => SortKind::Hex,
+                'f' => SortKind::Float,
+                'b' => SortKind::Binary,
+                'o' => SortKind::Octal,
+                '"' => break,
+                c if c.is_alphanumeric() || sort.pattern.is_some() => return Err(invalid().into()),
+                delimiter => {
+                    let (pattern, after) = crate::ex::split_pattern(rest, delimiter);
+                    sort.pattern = Some(pattern);
+                    rest = after;
+                    continue;
+                }
+            };
+            if sort.kind != SortKind::Text {
+                return Err(invalid().into());
+            }
+            sort.kind = kind;
+        }
+        Ok(sort)
+    }
+
+    fn key(&self, text: &str) -> Key {
+        let number = |radix, prefix| integer(text, radix, prefix);
+        match self.kind {
+            SortKind::Text if self.ignore_case => Key::Text(text.to_lowercase()),
+            SortKind::Text => Key::Text(text.to_string()),
+            SortKind::Decimal => Key::Integer(number(10, None)),
+            SortKind::Hex => Key::Integer(number(16, Some('x'))),
+            SortKind::Binary => Key::Integer(number(2, Some('b'))),
+            SortKind::Octal => Key::Integer(number(8, Some('o'))),
+            SortKind::Float => Key::Float(float(text)),
+        }
+    }
+}
+
+fn compare(a: &Key, b: &Key) -> Ordering {
+    match (a, b) {
+        (Key::Text(a), Key::Text(b)) => a.cmp(b),
+        (Key::Integer(a), Key::Integer(b)) => a.cmp(b),
+        (Key::Float(a), Key::Float(b)) => match (a, b) {
+            (Some(a), Some(b)) => a.total_cmp(b),
+            _ => a.is_some().cmp(&b.is_some()),
+        },
+        _ => Ordering::Equal,
+    }
+}
+
+/// The first number written in `radix` in `text`, negative when preceded
+/// by `-`, after an optional `0{prefix}`
+fn integer(text: &str, radix: u32, prefix: Option<char>) -> Option<i64> {
+    let start = text.find(|c: char| c.is_digit(radix))?;
+    let negative = text[..start].ends_with('-');
+    let mut digits = &text[start..];
+    if let Some(prefix) = prefix {
+        let after = digits
+            .strip_prefix('0')
+            .and_then(|rest| rest.strip_prefix([prefix, prefix.to_ascii_uppercase()]));
+        if let Some(after) = after.filter(|after| after.starts_with(|c: char| c.is_digit(radix))) {
+            digits = after;
+        }
+    }
+    let length = digits
+        .find(|c: char| !c.is_digit(radix))
+        .unwrap_or(digits.len());
+    let value = i64::from_str_radix(&digits[..length], radix).unwrap_or(i64::MAX);
+    Some(if negative { -value } else { value })
+}
+
+/// The first floating point number in `text`, like `-1.5e3` or `.5`
+fn float(text: &str) -> Option<f64> {
+    let bytes = text.as_bytes();
+    let digit_at = |index: usize| bytes.get(index).is_some_and(u8::is_ascii_digit);
+    let start = (0..bytes.len()).find(|&index| match bytes[index] {
+        b'0'..=b'9' => true,
+        b'.' => digit_at(index + 1),
+        b'-' => digit_at(index + 1) || (bytes.get(index + 1) == Some(&b'.') && digit_at(index + 2)),
+        _ => false,
+    })?;
+    let mut end = start + usize::from(bytes[start] == b'-');
+    let digits = |mut index: usize| {
+        while digit_at(index) {
+            index += 1;
+        }
+        index
+    };
+    end = digits(end);
+    if bytes.get(end) == Some(&b'.') {
+        end = digits(end + 1);
+    }
+    if matches!(bytes.get(end), Some(b'e' | b'E')) {
+        let sign = usize::from(matches!(bytes.get(end + 1), Some(b'+' | b'-')));
+        if digit_at(end + 1 + sign) {
+            end = digits(end + 1 + sign);
+        }
+    }
+    text[start..end].parse().ok()
+}
+
+impl Buffer {
+    /// Sorts the lines in `range` stably, returning how many lines `u`
+    /// removed
+    pub fn sort(&mut self, range: Range, sort: &Sort, regex: Option<&Regex>) -> usize {
+        let keys: Vec<Key> = (range.start..=range.end)
+            .map(|line| {
+                let raw = &self.contents[line].raw;
+                let text = match regex.and_then(|regex| regex.captures_from(self.contents.as_slice(), line, 0)) {
+                    Some(captures) => {
+                        let end = if captures.end().0 == line { captures.end().1 } else { raw.len() };
+                        if sort.use_match {
+                            &raw[captures.start().1..end]
+                        } else {
+                            &raw[end..]
+                        }
+                    }
+                    None if regex.is_some() => "",
+                    None => raw,
+                };
+                sort.key(text)
+            })
+            .collect();
+        let rows: Vec<Row> = self.contents.drain(range.start..=range.end).collect();
+        let mut sorted: Vec<(Key, Row)> = keys.into_iter().zip(rows).collect();
+        if sort.reverse {
+            sorted.sort_by(|(a, _), (b, _)| compare(b, a));
+        } else {
+            sorted.sort_by(|(a, _), (b, _)| compare(a, b));
+        }
+        let count = sorted.len();
+        if sort.unique {
+            sorted.dedup_by(|(a, _), (b, _)| compare(a, b) == Ordering::Equal);
+        }
+        let removed = count - sorted.len();
+        self.contents
+            .splice(range.start..range.start, sorted.into_iter().map(|(_, row)| row));
+        self.lines_replaced(range.start, count, count - removed);
+        self.changed((range.start, 0), (range.end - removed, 0));
+        removed
+    }
+}
@@ -0,0 +1,334 @@
+use super::search::Match;
+use super::{Buffer, Result, Row};
+use crate::ex::Range;
+use crate::regex::{self, Captures, Regex};
+
+#[derive(Clone, Copy, Default, Debug)]
+pub struct SubstituteFlags {
+    /// `g`: every match in the line instead of the first one
+    pub global: bool,
+    /// `c`: ask before each substitution
+    pub confirm: bool,
+    /// `n`: only count the matches
+    pub report_only: bool,
+    /// `e`: no error when nothing matches
+    pub ignore_errors: bool,
+    /// `i` or `I`: override the case sensitivity of the pattern
+    pub ignore_case: Option<bool>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Substitute {
+    pub pattern: String,
+    pub replacement: String,
+    pub flags: SubstituteFlags,
+}
+
+/// Answer to the prompt of the `c` flag
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Confirm {
+    Yes,
+    No,
+    All,
+    Quit,
+    Last,
+}
+
+#[derive(Default, Debug)]
+pub struct SubstituteResult {
+    pub substitutions: usize,
+    pub lines: usize,
+    /// Line of the last substitution, where the cursor goes
+    pub last_line: Option<usize>,
+}
+
+impl Substitute {
+    /// Parses what follows `:s`, e.g. `/pat/rep/g 3`, returning the count
+    /// too. Without a pattern, the previous substitute is repeated with the
+    /// flags given, `&` keeping its flags.
+    pub fn parse(argument: &str, previous: Option<&Substitute>) -> Result<(Self, Option<usize>)> {
+        let Some(delimiter) = argument
+            .chars()
+            .next()
+            .filter(|c| !c.is_alphanumeric() && !c.is_whitespace() && !"\"|&".contains(*c))
+        else {
+            let previous = previous.ok_or("E35: No previous regular expression")?;
+            let (flags, count) = parse_flags(argument, previous.flags)?;
+            let substitute = Self {
+                flags,
+                ..previous.clone()
+            };
+            return Ok((substitute, count));
+        };
+        let mut parts = split_delimited(&argument[delimiter.len_utf8()..], delimiter);
+        let pattern = parts.next().unwrap_or_default();
+        let replacement = parts.next().unwrap_or_default();
+        let rest = parts.next().unwrap_or_default();
+        let replacement = expand_tilde(
+            &replacement,
+            previous.map(|previous| &previous.replacement[..]),
+        );
+        let (flags, count) = parse_flags(
+            &rest,
+            previous.map(|previous| previous.flags).unwrap_or_default(),
+        )?;
+        Ok((
+            Self {
+                pattern,
+                replacement,
+                flags,
+            },
+            count,
+        ))
+    }
+
+    pub fn regex(&self) -> std::result::Result<Regex, regex::Error> {
+        Regex::with_case(&self.pattern, self.flags.ignore_case.unwrap_or(false))
+    }
+
+    /// The replacement text for a match, with `&`, `\1` and the case
+    /// modifiers applied. Line breaks (`\r`) come out as `\n`.
+    pub fn expand(&self, buffer: &Buffer, captures: &Captures) -> String {
+        let group = |index: usize| {
+            captures
+                .group(index)
+                .map(|(from, to)| regex::slice(buffer.contents.as_slice(), from, to))
+                .unwrap_or_default()
+        };
+        let mut result = String::new();
+        let mut once = None;
+        let mut until_end = None;
+        let mut chars = self.replacement.chars();
+        while let Some(c) = chars.next() {
+            let text = match c {
+                '&' => group(0),
+                '\r' => "\n".to_string(),
+                '\\' => match chars.next() {
+                    Some(digit @ '0'..='9') => group(digit.to_digit(10).unwrap() as usize),
+                    Some('n') => "\0".to_string(),
+                    Some('r') => "\n".to_string(),
+                    Some('t') => "\t".to_string(),
+                    Some(modifier @ ('u' | 'l' | 'U' | 'L' | 'e' | 'E')) => {
+                        match modifier {
+                            'u' => once = Some(Case::Upper),
+                            'l' => once = Some(Case::Lower),
+                            'U' => until_end = Some(Case::Upper),
+                            'L' => until_end = Some(Case::Lower),
+                            _ => until_end = None,
+                        }
+                        continue;
+                    }
+                    Some(other) => other.to_string(),
+                    None => "\\".to_string(),
+                },
+                c => c.to_string(),
+            };
+            for c in text.chars() {
+                match once.take().or(until_end) {
+                    Some(case) => case.apply(c, &mut result),
+                    None => result.push(c),
+                }
+            }
+        }
+        result
+    }
+}
+
+/// Case change requested by `\u`, `\l`, `\U` or `\L` in a replacement
+#[derive(Clone, Copy)]
+enum Case {
+    Upper,
+    Lower,
+}
+
+impl Case {
+    fn apply(self, c: char, result: &mut String) {
+        match self {
+            Case::Upper => result.extend(c.to_uppercase()),
+            Case::Lower => result.extend(c.to_lowercase()),
+        }
+    }
+}
+
+/// Splits `text` at unescaped `delimiter`s, turning `\{delimiter}` into the
+/// delimiter itself and keeping every other escape
+fn split_delimited(text: &str, delimiter: char) -> impl Iterator<Item = String> {
+    let mut parts = vec![String::new()];
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        let count = parts.len();
+        let part = parts.last_mut().unwrap();
+        if c == '\\' {
+            match chars.next() {
+                Some(next) if next == delimiter => part.push(next),
+                Some(next) => {
+                    part.push(c);
+                    part.push(next);
+                }
+                None => part.push(c),
+            }
+        } else if c == delimiter && count < 3 {
+            parts.push(String::new());
+        } else {
+            part.push(c);
+        }
+    }
+    parts.into_iter()
+}
+
+/// Replaces each unescaped `~` with the previous replacement string
+fn expand_tilde(replacement: &str, previous: Option<&str>) -> String {
+    let mut result = String::new();
+    let mut chars = replacement.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                result.push(c);
+                if let Some(next) = chars.next() {
+                    result.push(next);
+                }
+            }
+            '~' => result.push_str(previous.unwrap_or_default()),
+            c => result.push(c),
+        }
+    }
+    result
+}
+
+/// Parses `[&][cegiInp#lr] [count]`
+fn parse_flags(text: &str, previous: SubstituteFlags) -> Result<(SubstituteFlags, Option<usize>)> {
+    let text = text.trim_start();
+    let (mut flags, text) = match text.strip_prefix('&') {
+        Some(rest) => (previous, rest),
+        None => (SubstituteFlags::default(), text),
+    };
+    let length = text
+        .find(|c: char| !"cegiInp#lr".contains(c))
+        .unwrap_or(text.len());
+    for flag in text[..length].chars() {
+        match flag {
+            'c' => flags.confirm = true,
+            'e' => flags.ignore_errors = true,
+            'g' => flags.global = !flags.global,
+            'i' => flags.ignore_case = Some(true),
+            'I' => flags.ignore_case = Some(false),
+            'n' => flags.report_only = true,
+            _ => (),
+        }
+    }
+    let count = text[length..].trim();
+    if count.is_empty() {
+        return Ok((flags, None));
+    }
+    match count.parse::<usize>() {
+        Ok(count) if count > 0 => Ok((flags, Some(count))),
+        Ok(_) => Err("E939: Positive count required".into()),
+        Err(_) => Err("E488: Trailing characters".into()),
+    }
+}
+
+impl Buffer {
+    /// Runs a substitute over `range`, asking `confirm` before each
+    /// substitution when the `c` flag is given
+    pub fn substitute(
+        &mut self,
+        regex: &Regex,
+        substitute: &Substitute,
+        range: Range,
+        mut confirm: impl FnMut(&mut Buffer, Match) -> Result<Confirm>,
+    ) -> Result<SubstituteResult> {
+        let flags = substitute.flags;
+        let mut result = SubstituteResult::default();
+        let mut ask = flags.confirm;
+        let mut last = range.end as isize;
+        let mut line = range.start;
+        let mut at = 0;
+        let mut skip_empty_at = None;
+        let mut first_line = None;
+        while (line as isize) <= last && line < self.contents.len() {
+            let next_line = |line: usize| (line + 1, 0, None);
+            let Some(captures) = regex.captures_from(self.contents.as_slice(), line, at) else {
+                (line, at, skip_empty_at) = next_line(line);
+                continue;
+            };
+            let (start, end) = (captures.start(), captures.end());
+            if start.0 != line {
+                (line, at, skip_empty_at) = next_line(line);
+                continue;
+            }
+            let raw = &self.contents[line].raw;
+            let after_empty = start.1 + raw[start.1..].chars().next().map_or(1, char::len_utf8);
+            if start == end && skip_empty_at == Some(start.1) {
+                at = after_empty;
+                continue;
+            }
+            let mut stop = false;
+            if ask {
+                let found = Match {
+                    start: self.grapheme_position(start),
+                    end: self.grapheme_position(end),
+                };
+                match confirm(self, found)? {
+                    Confirm::Yes => (),
+                    Confirm::No => {
+                        if !flags.global {
+                            (line, at, skip_empty_at) = next_line(line);
+                        } else if start == end {
+                            at = after_empty;
+                        } else {
+                            (line, at, skip_empty_at) = (end.0, end.1, Some(end.1));
+                        }
+                        continue;
+                    }
+                    Confirm::All => ask = false,
+                    Confirm::Quit => break,
+                    Confirm::Last => stop = true,
+                }
+            }
+            result.substitutions += 1;
+            if result.last_line != Some(line) {
+                result.lines += 1;
+            }
+            result.last_line = Some(line);
+            let (after_line, after) = if flags.report_only {
+                (end.0, end.1)
+            } else {
+                let replacement = substitute.expand(self, &captures);
+                let before = self.contents[line].raw[..start.1].to_string() + &replacement;
+                let joined = before.clone() + &self.contents[end.0].raw[end.1..];
+                let mut rows: Vec<Row> = joined.split('\n').map(Row::new).collect();
+                rows[0].marked = self.contents[line].marked;
+                let added = rows.len();
+                self.contents.splice(line..=end.0, rows);
+                self.lines_replaced(line, end.0 - line + 1, added);
+                first_line.get_or_insert(line);
+                last += added as isize - (end.0 - line + 1) as isize;
+                let breaks = before.matches('\n').count();
+                let column = before.len() - before.rfind('\n').map_or(0, |index| index + 1);
+                result.last_line = Some(line + breaks);
+                (line + breaks, column)
+            };
+            if stop {
+                break;
+            }
+            if end.0 > start.0 {
+                // the text of the next line was pulled in: continue with it
+                (line, at, skip_empty_at) = (after_line, after, None);
+            } else if !flags.global {
+                (line, at, skip_empty_at) = next_line(after_line);
+            } else if start == end {
+                let raw = &self.contents[after_line].raw;
+                match raw[after..].chars().next() {
+                    Some(c) => (line, at, skip_empty_at) = (after_line, after + c.len_utf8(), None),
+                    None => (line, at, skip_empty_at) = next_line(after_line),
+                }
+            } else {
+                (line, at, skip_empty_at) = (after_line, after, Some(after));
+            }
+        }
+        if let (Some(first), Some(last)) = (first_line, result.last_line) {
+            self.changed((first, 0), (last, 0));
+        }
+        Ok(result)
+    }
+}
@@ -0,0 +1,256 @@
+use super::{Buffer, Row};
+use crate::ex::Range;
+use crate::register::{Register, RegisterKind};
+
+/// What Visual mode selects between its two ends
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SelectionKind {
+    /// `v`
+    Chars,
+    /// `V`
+    Lines,
+    /// `Ctrl-V`
+    Block,
+}
+
+/// A Visual mode selection, between the position where it started and the
+/// cursor, both as (line, grapheme) and in either order
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Selection {
+    pub kind: SelectionKind,
+    pub anchor: (usize, usize),
+    pub cursor: (usize, usize),
+    /// Set by `$` in block mode: every line is selected up to its end
+    pub to_end: bool,
+}
+
+impl Selection {
+    /// The first and last position, in buffer order
+    pub fn bounds(&self) -> ((usize, usize), (usize, usize)) {
+        (self.anchor.min(self.cursor), self.anchor.max(self.cursor))
+    }
+
+    pub fn lines(&self) -> Range {
+        Range {
+            start: self.anchor.0.min(self.cursor.0),
+            end: self.anchor.0.max(self.cursor.0),
+        }
+    }
+
+    /// The screen columns a block covers, both included
+    pub fn columns(&self, buffer: &Buffer) -> (usize, usize) {
+        let span = |(line, x): (usize, usize)| {
+            let row = &buffer.contents[line];
+            let x = x.min(row.len());
+            let start = row.visual_distance(0, x);
+            let width = if x < row.len() { row.visual_distance(x, x + 1) } else { 1 };
+            (start, start + width.max(1) - 1)
+        };
+        let (anchor, cursor) = (span(self.anchor), span(self.cursor));
+        let right = if self.to_end { usize::MAX } else { anchor.1.max(cursor.1) };
+        (anchor.0.min(cursor.0), right)
+    }
+}
+
+impl Row {
+    /// The graphemes overlapping the screen columns `left..=right`
+    pub fn graphemes_in_columns(&self, left: usize, right: usize) -> (usize, usize) {
+        let mut column = 0;
+        let mut from = None;
+        let mut to = 0;
+        for x in 0..self.len() {
+            let width = self.visual_distance(x, x + 1).max(1);
+            if column + width > left && column <= right {
+                from.get_or_insert(x);
+                to = x + 1;
+            }
+            column += width;
+        }
+        match from {
+            Some(from) => (from, to),
+            None => (self.len(), self.len()),
+        }
+    }
+
+    /// The text of a block on this row, with the columns of graphemes only
+    /// partly inside it (like tabs) turned into spaces, and the number of
+    /// columns of those graphemes left outside, before and after the block
+    fn block_text(&self, left: usize, right: usize) -> (String, usize, usize) {
+        let (from, to) = self.graphemes_in_columns(left, right);
+        let graphemes = self.raw_graphemes();
+        let mut text = String::new();
+        let (mut before, mut after) = (0, 0);
+        let mut column = self.visual_distance(0, from);
+        for (x, grapheme) in graphemes.iter().enumerate().take(to).skip(from) {
+            let end = column + self.visual_distance(x, x + 1).max(1) - 1;
+            if column >= left && end <= right {
+                text.push_str(grapheme);
+            } else {
+                before += left.saturating_sub(column);
+                after += end.saturating_sub(right);
+                text.push_str(&" ".repeat(end.min(right) + 1 - column.max(left)));
+            }
+            column = end + 1;
+        }
+        (text, before, after)
+    }
+}
+
+impl Buffer {
+    /// The graphemes of `line` in the selection, as an exclusive range, and
+    /// whether the line break after them is selected too
+    pub fn selected_graphemes(&self, selection: &Selection, line: usize) -> Option<(usize, usize, bool)> {
+        let lines = selection.lines();
+        if line < lines.start || line > lines.end {
+            return None;
+        }
+        let row = &self.contents[line];
+        match selection.kind {
+            SelectionKind::Lines => Some((0, row.len(), true)),
+            SelectionKind::Block => {
+                let (left, right) = selection.columns(self);
+                let (from, to) = row.graphemes_in_columns(left, right);
+                Some((from, to, false))
+            }
+            SelectionKind::Chars => {
+                let (start, end) = selection.bounds();
+                let from = if line == start.0 { start.1.min(row.len()) } else { 0 };
+                let (to, line_break) = if line == end.0 {
+                    let to = end.1 + 1;
+                    (to.min(row.len()), to > row.len())
+                } else {
+                    (row.len(), true)
+                };
+                Some((from, to, line_break))
+            }
+        }
+    }
+
+    /// The byte range of `line` a selection covers, line break excluded
+    fn selected_bytes(&self, selection: &Selection, line: usize) -> Option<(usize, usize)> {
+        let (from, to, _) = self.selected_graphemes(selection, line)?;
+        let row = &self.contents[line];
+        Some((row.byte_idx_of(from), row.byte_idx_of(to)))
+    }
+
+    /// The selected text, as it goes into a register
+    pub fn selection_text(&self, selection: &Selection) -> Register {
+        let range = selection.lines();
+        let (left, right) = selection.columns(self);
+        let mut lines: Vec<String> = (range.start..=range.end)
+            .map(|line| {
+                if selection.kind == SelectionKind::Block {
+                    return self.contents[line].block_text(left, right).0;
+                }
+                let (from, to) = self.selected_bytes(selection, line).unwrap();
+                self.contents[line].raw[from..to].to_string()
+            })
+            .collect();
+        let kind = match selection.kind {
+            SelectionKind::Lines => RegisterKind::Linewise,
+            SelectionKind::Block => RegisterKind::Blockwise,
+            SelectionKind::Chars => {
+                if let Some((_, _, true)) = self.selected_graphemes(selection, range.end) {
+                    lines.push(String::new());
+                }
+                RegisterKind::Charwise
+            }
+        };
+        Register { lines, kind }
+    }
+
+    /// Deletes the selected text, leaving the cursor where it started
+    pub fn delete_selection(&mut self, selection: &Selection) {
+        let range = selection.lines();
+        match selection.kind {
+            SelectionKind::Lines => self.delete_lines(range),
+            SelectionKind::Block => {
+                let (left, right) = selection.columns(self);
+                for row in &mut self.contents[range.start..=range.end] {
+                    let (from, to) = row.graphemes_in_columns(left, right);
+                    let (_, before, after) = row.block_text(left, right);
+                    let (from, to) = (row.byte_idx_of(from), row.byte_idx_of(to));
+                    let mut raw = std::mem::take(&mut row.raw);
+                    raw.replace_range(from..to, &" ".repeat(before + after));
+                    row.set_raw(raw);
+                }
+                let row = &self.contents[range.start];
+                let x = row.graphemes_in_columns(left, right).0;
+                self.changed((range.start, x), (range.end, x));
+                self.set_cursor_position((range.start, x));
+            }
+            SelectionKind::Chars => {
+                let (start, _) = selection.bounds();
+                let (_, to, line_break) = self.selected_graphemes(selection, range.end).unwrap();
+                let end = if line_break && range.end + 1 < self.contents.len() {
+                    (range.end + 1, 0)
+                } else {
+                    (range.end, to)
+                };
+                let from = (start.0, start.1.min(self.contents[start.0].len()));
+                self.delete_range(from, end, super::MotionKind::Exclusive).ok();
+            }
+        }
+    }
+
+    /// Replaces the selected text of every line with `f` applied to it
+    pub fn map_selection(&mut self, selection: &Selection, f: impl Fn(&str) -> String) {
+        let range = selection.lines();
+        for line in range.start..=range.end {
+            let (from, to) = self.selected_bytes(selection, line).unwrap();
+            let row = &mut self.contents[line];
+            let mut raw = std::mem::take(&mut row.raw);
+            let mapped = f(&raw[from..to]);
+            raw.replace_range(from..to, &mapped);
+            row.set_raw(raw);
+        }
+        let (start, end) = selection.bounds();
+        self.changed(start, end);
+    }
+
+    /// The number of screen columns `line` takes
+    pub fn line_width(&self, line: usize) -> usize {
+        let row = &self.contents[line];
+        row.visual_distance(0, row.len())
+    }
+
+    /// The number of graphemes in `line`
+    pub fn line_length(&self, line: usize) -> usize {
+        self.contents[line].len()
+    }
+
+    /// The first grapheme of `line` at or after screen `column`
+    pub fn grapheme_at_column(&self, line: usize, column: usize) -> usize {
+        let row = &self.contents[line];
+        (0..=row.len())
+            .find(|x| row.visual_distance(0, *x) >= column)
+            .unwrap_or(row.len())
+    }
+
+    /// The graphemes of `line` between `from` and `to`
+    pub fn grapheme_text(&self, line: usize, from: usize, to: usize) -> String {
+        let row = &self.contents[line];
+        let to = to.min(row.len());
+        row.raw[row.byte_idx_of(from.min(to))..row.byte_idx_of(to)].to_string()
+    }
+
+    /// Inserts `text` in `line` before the first grapheme at or after screen
+    /// `column`, padding the line with spaces up to it when it is shorter
+    /// and `pad` is set. `None` for `column` appends to the line.
+    pub fn insert_at_column(&mut self, line: usize, column: Option<usize>, text: &str, pad: bool) {
+        let width = self.line_width(line);
+        let x = column.map(|column| self.grapheme_at_column(line, column));
+        let row = &mut self.contents[line];
+        let (at, padding) = match column {
+            Some(column) if column <= width => (row.byte_idx_of(x.unwrap()), 0),
+            Some(column) if pad => (row.raw.len(), column - width),
+            Some(_) => return,
+            None => (row.raw.len(), 0),
+        };
+        let mut raw = std::mem::take(&mut row.raw);
+        raw.insert_str(at, &(" ".repeat(padding) + text));
+        row.set_raw(raw);
+        let x = row.grapheme_idx_of(at);
+        self.changed((line, x), (line, x));
+    }
+}
@@ -0,0 +1,210 @@
+//! Soft wrapping: long lines shown over several screen lines
+
+use super::row::Row;
+use super::Buffer;
+
+/// How lines wrap, as the options say
+#[derive(Clone, Default)]
+pub struct Wrap {
+    /// `linebreak`: break after a blank or punctuation rather than at the
+    /// last column that fits
+    pub linebreak: bool,
+    /// `breakindent`: indent the continuation lines as much as the line
+    pub breakindent: bool,
+    /// `showbreak`: shown at the start of the continuation lines
+    pub showbreak: String,
+}
+
+/// What `linebreak` breaks after, as Vim's default 'breakat'
+const BREAK_AT: &str = " \t!@*-+;:,./?";
+
+/// A screen line of a wrapped line: its graphemes and what comes before
+/// them
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct Segment {
+    pub start: usize,
+    pub end: usize,
+    /// The columns of indent and `showbreak` before a continuation line
+    pub prefix: usize,
+}
+
+impl Wrap {
+    /// How many columns the indent and `showbreak` of the continuation lines
+    /// of `row` take, leaving a column at least in `width`
+    fn prefix(&self, row: &Row, width: usize) -> usize {
+        let indent = if self.breakindent {
+            let blanks = row.raw_graphemes().iter().take_while(|grapheme| grapheme.trim().is_empty()).count();
+            row.visual_distance(0, blanks)
+        } else {
+            0
+        };
+        (indent + self.showbreak.chars().count()).min(width.saturating_sub(1))
+    }
+
+    /// The screen lines `row` takes in a window `width` columns wide
+    pub fn segments(&self, row: &Row, width: usize) -> Vec<Segment> {
+        let widths = row.widths();
+        let graphemes = row.raw_graphemes();
+        let continued = self.prefix(row, width);
+        let mut segments = Vec::new();
+        let mut start = 0;
+        let mut prefix = 0;
+        loop {
+            let room = width - prefix;
+            let mut end = start;
+            let mut used = 0;
+            let mut last_break = None;
+            while end < widths.len() && used + widths[end] <= room {
+                used += widths[end];
+                if self.linebreak && BREAK_AT.contains(graphemes[end]) {
+                    last_break = Some(end + 1);
+                }
+                end += 1;
+            }
+            if end < widths.len() {
+                end = last_break.unwrap_or(end).max(start + 1);
+            }
+            segments.push(Segment { start, end, prefix });
+            if end >= widths.len() {
+                return segments;
+            }
+            start = end;
+            prefix = continued;
+        }
+    }
+
+    /// What goes before the graphemes of a continuation line with `prefix`
+    /// columns before it: the indent, then `showbreak`
+    pub fn prefix_text(&self, prefix: usize) -> (String, String) {
+        let showbreak: String = self.showbreak.chars().take(prefix).collect();
+        (" ".repeat(prefix - showbreak.chars().count()), showbreak)
+    }
+}
+
+impl Buffer {
+    /// The screen lines `line` takes in a window `width` columns wide, one
+    /// without `wrap`
+    pub fn segments(&self, line: usize, width: usize, wrap: Option<&Wrap>) -> Vec<Segment> {
+        let row = &self.contents[line];
+        match wrap {
+            Some(wrap) if width > 0 => wrap.segments(row, width),
+            _ => vec![Segment {
+                start: 0,
+                end: row.len(),
+                prefix: 0,
+            }],
+        }
+    }
+
+    /// The top line to show in a window `rows` high and `width` wide for
+    /// `cursor_line` to be in full view, when `top` was shown first
+    pub fn visible_top(&self, cursor_line: usize, top: usize, (rows, width): (usize, usize), wrap: Option<&Wrap>) -> usize {
+        let mut top = top.min(cursor_line);
+        let height = |line| self.segments(line, width, wrap).len();
+        let mut used: usize = (top..=cursor_line).map(height).sum();
+        while top < cursor_line && used > rows {
+            used -= height(top);
+            top += 1;
+        }
+        top
+    }
+
+    /// Scrolls for the cursor line to be in full view, lines taking as many
+    /// screen lines as they wrap over
+    pub fn scroll_to_cursor(&mut self, (rows, width): (usize, usize), wrap: Option<&Wrap>) {
+        let (line, x) = self.cursor_position();
+        let top = self.visible_top(line, self.offset, (rows, width), wrap);
+        if top != self.offset {
+            self.offset = top;
+            self.cursor.y = line - top;
+            self.cursor.x = x;
+        }
+    }
+
+    /// Where the cursor is on the screen, as (row, column) from the top left
+    /// of the text, lines wrapping as `wrap` says
+    pub fn wrapped_screen_position(&self, width: usize, wrap: Option<&Wrap>) -> (usize, usize) {
+        let Some(wrap) = wrap else {
+            return self.screen_position();
+        };
+        let (line, x) = self.cursor_position();
+        let above: usize = (self.offset..line).map(|line| self.segments(line, width, Some(wrap)).len()).sum();
+        let (index, segment) = self.segment_of(line, x, width, wrap);
+        let column = segment.prefix + self.contents[line].visual_distance(segment.start, x.min(self.contents[line].len()));
+        (above + index, column.min(width.saturating_sub(1)))
+    }
+
+    /// The screen line of `line` that grapheme `x` is on, and its index
+    fn segment_of(&self, line: usize, x: usize, width: usize, wrap: &Wrap) -> (usize, Segment) {
+        let segments = self.segments(line, width, Some(wrap));
+        let index = segments.iter().position(|segment| x < segment.end).unwrap_or(segments.len() - 1);
+        (index, segments[index])
+    }
+
+    /// `gj` and `gk`: the position `count` screen lines down, or up for a
+    /// negative `count`, as near the cursor's column as the text allows
+    pub fn screen_line_motion(&self, count: isize, width: usize, wrap: Option<&Wrap>) -> (usize, usize) {
+        let (mut line, x) = self.cursor_position();
+        let Some(wrap) = wrap else {
+            let line = line.saturating_add_signed(count).min(self.contents.len() - 1);
+            return (line, x.min(self.contents[line].len().saturating_sub(1)));
+        };
+        let (mut index, segment) = self.segment_of(line, x, width, wrap);
+        let row = &self.contents[line];
+        let column = segment.prefix + row.visual_distance(segment.start, x.min(row.len()));
+        for _ in 0..count.unsigned_abs() {
+            if count > 0 {
+                if index + 1 < self.segments(line, width, Some(wrap)).len() {
+                    index += 1;
+                } else if line + 1 < self.contents.len() {
+                    line += 1;
+                    index = 0;
+                }
+            } else if index > 0 {
+                index -= 1;
+            } else if line > 0 {
+                line -= 1;
+                index = self.segments(line, width, Some(wrap)).len() - 1;
+            }
+        }
+        let segment = self.segments(line, width, Some(wrap))[index];
+        let row = &self.contents[line];
+        let mut x = segment.start;
+        let mut at = segment.prefix;
+        while x + 1 < segment.end && at + row.widths()[x] <= column {
+            at += row.widths()[x];
+            x += 1;
+        }
+        (line, x)
+    }
+
+    /// `g0` and `g$`: the first or the last grapheme of the cursor's screen
+    /// line, the one at the left or right edge of the window without `wrap`
+    pub fn screen_line_edge(&self, end: bool, width: usize, wrap: Option<&Wrap>) -> (usize, usize) {
+        let (line, x) = self.cursor_position();
+        let row = &self.contents[line];
+        let last = row.len().saturating_sub(1);
+        match wrap {
+            Some(wrap) => {
+                let (_, segment) = self.segment_of(line, x, width, wrap);
+                if end {
+                    (line, segment.end.saturating_sub(1).max(segment.start).min(last))
+                } else {
+                    (line, segment.start.min(last))
+                }
+            }
+            None => {
+                let first = row.grapheme_at_column(self.left);
+                let first = if row.visual_distance(0, first) < self.left { first + 1 } else { first };
+                if !end {
+                    return (line, first.min(last));
+                }
+                let mut x = row.grapheme_at_column(self.left + width.saturating_sub(1));
+                if x > first && row.visual_distance(0, x + 1) > self.left + width {
+                    x -= 1;
+                }
+                (line, x.min(last))
+            }
+        }
+    }
+}
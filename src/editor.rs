@@ -1,89 +1,2427 @@
 use std::io::{stdout, Write};
-use std::{collections::HashMap, default, fs::read, path::PathBuf};
+use std::fs::{metadata, read};
 
 use crossterm::cursor::{MoveTo, self};
-use crossterm::event::{self, Event, KeyEvent, KeyModifiers};
-use crossterm::style::Print;
-use crossterm::{terminal::{*, self}, ExecutableCommand, execute, queue};
+use crossterm::event::{self, KeyEvent, KeyModifiers};
+use crossterm::style::{Attribute, Color, Print, ResetColor, SetAttribute, SetBackgroundColor, SetForegroundColor};
+use crossterm::{terminal::{*, self}, QueueableCommand, execute, queue};
 
-use crate::buffer::Buffer;
+use crate::arglist::{self, ArgList};
+use crate::buffer::gutter::{sign_width, Gutter, SIGN_WIDTH};
+use crate::buffer::marks::{self, Marks};
+use crate::buffer::search::{Direction, Match, Search};
+use crate::buffer::sort::Sort;
+use crate::buffer::substitute::{Confirm, Substitute};
+use crate::buffer::visual::{Selection, SelectionKind};
+use crate::buffer::wrap::Wrap;
+use crate::buffer::{Buffer, Highlights, MotionKind};
+use crate::ex::{self, Command, Context, Range};
+use crate::input::{self, Typeahead};
+use crate::jumplist::JumpList;
+use crate::options::{self, Options};
+use crate::regex;
+use crate::register::{Register, RegisterKind, Registers};
+use crate::shell;
+use crate::statusline::{self, Status};
+use crate::window::{Area, Layout, TabPage, Window};
 
 pub type Error = Box<dyn std::error::Error>;
 pub type Result<T> = std::result::Result<T, Error>;
 
-#[derive(Default)]
+/// How many messages `:messages` keeps
+const MESSAGE_HISTORY: usize = 200;
+
+/// Shown under a message too long for the last line
+const PRESS_ENTER: &str = "Press ENTER or type command to continue";
+
+#[derive(Clone, Copy, PartialEq, Eq, Default)]
 enum OperationMode {
     #[default]
     Command,
     Insert,
-    Escape,
+    /// `R`: typed characters overwrite the text
+    Replace,
+    Visual(SelectionKind),
+}
+
+pub struct Editor {
+    wants_out: bool,
+    mode: OperationMode,
+    buffers: Vec<(String, Buffer)>,
+    buffer_index: usize,
+    /// The buffer `Ctrl-^` goes back to, the one last switched from
+    alternate: Option<usize>,
+    args: ArgList,
+    windows: Vec<Window>,
+    /// The current window, which shows `buffer_index`
+    window: usize,
+    layout: Layout,
+    /// Where the text of the current window goes on the screen
+    area: Area,
+    /// The tab pages besides the current one, whose windows are the ones
+    /// above
+    tabs: Vec<TabPage>,
+    /// Where the current tab page goes among the others
+    tab: usize,
+    last_line: CommandLine,
+    last_search: Option<Search>,
+    last_substitute: Option<Substitute>,
+    count: Option<usize>,
+    message: Option<String>,
+    /// Shown after the message, in the error color
+    error: Option<String>,
+    /// What `:messages` shows, the oldest first
+    messages: Vec<String>,
+    /// The signs `:sign define` named, with their text
+    sign_definitions: Vec<(String, String)>,
+    options: Options,
+    /// Set by `:nohlsearch` until the next search
+    no_highlight: bool,
+    registers: Registers,
+    typeahead: Typeahead,
+    /// Set while `:global` runs its command, which may not nest
+    in_global: bool,
+    /// What `!` stands for in the next shell command
+    last_shell_command: Option<String>,
+    /// Where the Visual mode selection started
+    visual_anchor: (usize, usize),
+    /// Set by `$` in Visual block mode
+    visual_to_end: bool,
+    /// The last selection, which `gv` brings back between `'<` and `'>`
+    last_visual: Option<Selection>,
+    /// Set while Insert mode was started from Visual block mode
+    block_insert: Option<BlockInsert>,
+    /// Where jumps in the current window came from, for `Ctrl-O` and
+    /// `Ctrl-I`; the other windows keep their own
+    jumps: JumpList,
+    /// What Replace mode overwrote, for Backspace to put back
+    replaced: Vec<String>,
+}
+
+/// A Visual block `I`, `A` or `c` waiting for Insert mode to end, to repeat
+/// what was typed on the other lines of the block
+struct BlockInsert {
+    /// Where typing started
+    start: (usize, usize),
+    /// The length of that line and of the buffer then, to find what was
+    /// typed
+    length: usize,
+    line_count: usize,
+    /// The other lines of the block that get the text
+    lines: Vec<usize>,
+    /// The screen column the text goes to, `None` for the end of each line
+    column: Option<usize>,
+    /// Whether shorter lines are padded with spaces up to `column`
+    pad: bool,
 }
 
-pub struct Editor {
-    wants_out: bool,
-    mode: OperationMode,
-    buffers: Vec<(String, Buffer)>,
-    buffer_index: usize,
-    last_line: CommandLine,
-}
+impl BlockInsert {
+    fn new(buffer: &Buffer, lines: Vec<usize>, column: Option<usize>, pad: bool) -> Self {
+        let start = buffer.cursor_position();
+        Self {
+            start,
+            length: buffer.line_length(start.0),
+            line_count: buffer.line_count(),
+            lines,
+            column,
+            pad,
+        }
+    }
+}
+
+impl Editor {
+    pub fn new() -> Self {
+        Self {
+            wants_out: false,
+            mode: OperationMode::default(),
+            buffers: Vec::new(),
+            buffer_index: 0,
+            alternate: None,
+            args: ArgList::default(),
+            windows: vec![Window::new(0)],
+            window: 0,
+            layout: Layout::default(),
+            area: Area::default(),
+            tabs: Vec::new(),
+            tab: 0,
+            last_line: CommandLine { history: Vec::new() },
+            last_search: None,
+            last_substitute: None,
+            count: None,
+            message: None,
+            error: None,
+            messages: Vec::new(),
+            sign_definitions: Vec::new(),
+            options: Options::default(),
+            no_highlight: false,
+            registers: Registers::default(),
+            typeahead: Typeahead::default(),
+            in_global: false,
+            last_shell_command: None,
+            visual_anchor: (0, 0),
+            visual_to_end: false,
+            last_visual: None,
+            block_insert: None,
+            jumps: JumpList::default(),
+            replaced: Vec::new(),
+        }
+    }
+
+    pub fn run(&mut self) -> Result<()> {
+        while !self.wants_out {
+            self.draw()?;
+            let keypress = self.capture_keypress();
+            if self.must_press_enter()? && matches!(keypress.code, event::KeyCode::Enter | event::KeyCode::Char(' ')) {
+                self.clear_message();
+                continue;
+            }
+            if let Err(error) = self.process_keypress(keypress) {
+                self.error = Some(error.to_string());
+            }
+        }
+        Ok(())
+    }
+
+    /// Redraws the windows and the message line
+    fn draw(&mut self) -> Result<()> {
+        let areas = self.update_layout()?;
+        let (columns, rows) = screen_size()?;
+        let rows = rows - 1;
+        let status = self.has_status_lines();
+        let regex = self
+            .last_search
+            .as_ref()
+            .filter(|_| self.options.hlsearch && !self.no_highlight)
+            .and_then(|search| search.regex().ok());
+        let highlights = Highlights {
+            search: regex.as_ref(),
+            visual: self.selection(),
+            ..Default::default()
+        };
+        let mut stdout = stdout();
+        stdout.queue(Clear(ClearType::All))?;
+        for (index, area) in areas.iter().enumerate() {
+            if index != self.window {
+                let window = &self.windows[index];
+                let buffer = &self.buffers[window.buffer].1;
+                let area = text_area(*area, status);
+                let ((line, x), top) = self.window_view(index, area);
+                let width = area.width.saturating_sub(buffer.gutter_width(&self.gutter()));
+                let left = buffer.visible_left((line, x), window.left(), width, self.sidescroll());
+                let highlights = Highlights {
+                    search: regex.as_ref(),
+                    ..Default::default()
+                };
+                buffer.draw_lines(area, (top, left), line, (&self.gutter(), self.wrap().as_ref()), &highlights)?;
+            }
+            if status {
+                self.draw_status_line(index, *area)?;
+            }
+            if area.left + area.width < columns {
+                let column: u16 = (area.left + area.width).try_into()?;
+                for row in area.top..area.top + area.height {
+                    let row: u16 = row.try_into()?;
+                    queue!(stdout, MoveTo(column, row), SetAttribute(Attribute::Reverse), Print('|'))?;
+                    queue!(stdout, SetAttribute(Attribute::Reset))?;
+                }
+            }
+        }
+        if !self.tabs.is_empty() {
+            self.draw_tab_line(columns)?;
+        }
+        let wrap = self.wrap();
+        self.buffers[self.buffer_index].1.draw_rows(self.area, (&self.gutter(), wrap.as_ref()), &highlights)?;
+        queue!(stdout, cursor::SavePosition)?;
+        let mut last_line = 0;
+        if self.message.is_some() || self.error.is_some() {
+            let mut lines = self.message_lines();
+            if self.must_press_enter()? {
+                lines.push((PRESS_ENTER, false));
+            }
+            let first_row: u16 = (rows + 1).saturating_sub(lines.len()).try_into()?;
+            last_line = match lines.as_slice() {
+                [(line, _)] => line.chars().count(),
+                _ => columns,
+            };
+            for (row, (line, error)) in (first_row..).zip(lines) {
+                queue!(stdout, MoveTo(0, row), Clear(ClearType::CurrentLine))?;
+                if error {
+                    queue!(stdout, SetForegroundColor(Color::White), SetBackgroundColor(Color::Red))?;
+                }
+                queue!(stdout, Print(line), ResetColor)?;
+            }
+        } else if let Some(mode) = self.mode_text().filter(|_| self.options.showmode) {
+            last_line = mode.len();
+            let row: u16 = rows.try_into()?;
+            queue!(stdout, MoveTo(0, row), SetAttribute(Attribute::Bold), Print(mode))?;
+            queue!(stdout, SetAttribute(Attribute::Reset))?;
+        }
+        let ruler_column = columns.saturating_sub(statusline::RULER_WIDTH);
+        if self.options.ruler && !status && last_line < ruler_column {
+            let status = self.status(self.window, self.area);
+            let ruler = statusline::render(statusline::RULER, &status, statusline::RULER_WIDTH - 1);
+            let (column, row): (u16, u16) = (ruler_column.try_into()?, rows.try_into()?);
+            queue!(stdout, MoveTo(column, row), Print(ruler))?;
+        }
+        queue!(stdout, cursor::RestorePosition)?;
+        stdout.flush()?;
+        Ok(())
+    }
+
+    /// The lines of the message and of the error, each with whether it is
+    /// one of the error's
+    fn message_lines(&self) -> Vec<(&str, bool)> {
+        let message = self.message.iter().flat_map(|message| message.lines().map(|line| (line, false)));
+        let error = self.error.iter().flat_map(|error| error.lines().map(|line| (line, true)));
+        message.chain(error).collect()
+    }
+
+    /// Whether the message is too long for the last line, so that it stays
+    /// until Enter is pressed
+    fn must_press_enter(&self) -> Result<bool> {
+        let lines = self.message_lines();
+        let columns = screen_size()?.0;
+        Ok(lines.len() > 1 || lines.first().is_some_and(|(line, _)| line.chars().count() > columns))
+    }
+
+    /// Clears the message line, keeping what it showed for `:messages`
+    /// but for listings and repeats
+    fn clear_message(&mut self) {
+        let message = self.message.take().filter(|message| !message.contains('\n'));
+        for message in message.into_iter().chain(self.error.take()) {
+            if self.messages.last() != Some(&message) {
+                self.messages.push(message);
+            }
+        }
+        let excess = self.messages.len().saturating_sub(MESSAGE_HISTORY);
+        self.messages.drain(..excess);
+    }
+
+    /// What `showmode` shows on the last line in the mode
+    fn mode_text(&self) -> Option<&'static str> {
+        match self.mode {
+            OperationMode::Command => None,
+            OperationMode::Insert => Some("-- INSERT --"),
+            OperationMode::Replace => Some("-- REPLACE --"),
+            OperationMode::Visual(SelectionKind::Chars) => Some("-- VISUAL --"),
+            OperationMode::Visual(SelectionKind::Lines) => Some("-- VISUAL LINE --"),
+            OperationMode::Visual(SelectionKind::Block) => Some("-- VISUAL BLOCK --"),
+        }
+    }
+
+    /// What the windows show left of their text, from the options
+    fn gutter(&self) -> Gutter {
+        Gutter {
+            number: self.options.number,
+            relative: self.options.relativenumber,
+            number_width: self.options.numberwidth,
+            signs: match self.options.signcolumn.as_str() {
+                "yes" => Some(true),
+                "no" => Some(false),
+                _ => None,
+            },
+        }
+    }
+
+    /// How many columns of text the current window shows, right of the
+    /// gutter
+    fn text_width(&self) -> usize {
+        let gutter = self.buffers[self.buffer_index].1.gutter_width(&self.gutter());
+        self.area.width.saturating_sub(gutter)
+    }
+
+    /// How long lines wrap, from the options, `None` for `nowrap`
+    fn wrap(&self) -> Option<Wrap> {
+        self.options.wrap.then(|| Wrap {
+            linebreak: self.options.linebreak,
+            breakindent: self.options.breakindent,
+            showbreak: self.options.showbreak.clone(),
+        })
+    }
+
+    /// `sidescroll` and `sidescrolloff`
+    fn sidescroll(&self) -> (usize, usize) {
+        (self.options.sidescroll, self.options.sidescrolloff)
+    }
+
+    /// Whether windows have a status line, as `laststatus` says
+    fn has_status_lines(&self) -> bool {
+        match self.options.laststatus {
+            0 => false,
+            1 => self.windows.len() > 1,
+            _ => true,
+        }
+    }
+
+    /// The cursor and top line of window `index`, with `area` for its text,
+    /// scrolled so that the cursor is in view
+    fn window_view(&self, index: usize, area: Area) -> ((usize, usize), usize) {
+        if index == self.window {
+            return self.buffers[self.buffer_index].1.view();
+        }
+        let window = &self.windows[index];
+        let buffer = &self.buffers[window.buffer].1;
+        let ((line, x), top) = window.view(buffer).unwrap_or_default();
+        let width = area.width.saturating_sub(buffer.gutter_width(&self.gutter()));
+        ((line, x), buffer.visible_top(line, top, (area.height, width), self.wrap().as_ref()))
+    }
+
+    /// What the items of `statusline` stand for in window `index`, with
+    /// `area` for its text
+    fn status(&self, index: usize, area: Area) -> Status<'_> {
+        let (path, buffer) = &self.buffers[self.window_buffer(index)];
+        let ((line, x), top) = self.window_view(index, area);
+        let height = area.height;
+        let (column, virtual_column) = buffer.columns((line, x));
+        Status {
+            path: display_name(path),
+            modified: buffer.is_modified(),
+            readonly: buffer.is_readonly(),
+            line: line + 1,
+            lines: buffer.line_count(),
+            column,
+            virtual_column,
+            top,
+            height,
+            fileformat: buffer.file_format(),
+            fileencoding: "utf-8",
+        }
+    }
+
+    /// The line under a window, from the `statusline` format, for the
+    /// current window in bold
+    fn draw_status_line(&self, index: usize, area: Area) -> Result<()> {
+        let format = match self.options.statusline.as_str() {
+            "" => statusline::DEFAULT,
+            format => format,
+        };
+        let status = self.status(index, text_area(area, true));
+        let text = statusline::render(format, &status, area.width);
+        let mut stdout = stdout();
+        let (row, column): (u16, u16) = ((area.top + area.height - 1).try_into()?, area.left.try_into()?);
+        queue!(stdout, MoveTo(column, row), SetAttribute(Attribute::Reverse))?;
+        if index == self.window {
+            queue!(stdout, SetAttribute(Attribute::Bold))?;
+        }
+        queue!(stdout, Print(text), SetAttribute(Attribute::Reset))?;
+        Ok(())
+    }
+
+    /// Lays the windows out on the screen, returning where each one goes,
+    /// and keeps the cursor of the current one in view
+    fn update_layout(&mut self) -> Result<Vec<Area>> {
+        let (columns, rows) = screen_size()?;
+        let tab_line = usize::from(!self.tabs.is_empty());
+        let screen = Area {
+            top: tab_line,
+            left: 0,
+            height: rows.saturating_sub(1 + tab_line),
+            width: columns,
+        };
+        let areas = self.layout.areas(screen, self.windows.len());
+        self.area = text_area(areas[self.window], self.has_status_lines());
+        let (width, sidescroll, wrap) = (self.text_width(), self.sidescroll(), self.wrap());
+        let buffer = &mut self.buffers[self.buffer_index].1;
+        buffer.scroll_to_cursor((self.area.height, width), wrap.as_ref());
+        match wrap {
+            Some(_) => buffer.set_left_column(0),
+            None => buffer.scroll_sideways(width, sidescroll),
+        }
+        Ok(areas)
+    }
+
+    /// The buffer window `index` shows
+    fn window_buffer(&self, index: usize) -> usize {
+        if index == self.window {
+            self.buffer_index
+        } else {
+            self.windows[index].buffer
+        }
+    }
+
+    /// Makes window `index` the current one
+    fn go_to_window(&mut self, index: usize) {
+        if index != self.window {
+            self.windows[self.window].leave(self.buffer_index, &mut self.buffers[self.buffer_index].1, &mut self.jumps);
+            self.window = index;
+            self.buffer_index = self.windows[index].enter(&mut self.buffers, &mut self.jumps);
+        }
+    }
+
+    /// `:split` and `:vsplit`: splits the current window in two, the new
+    /// one above or on the left becoming current with a copy of the jump
+    /// list, editing `file` if given
+    fn split_window(&mut self, vertical: bool, file: &str) -> Result<()> {
+        let area = self.update_layout()?[self.window];
+        let extent = if vertical { area.width } else { area.height };
+        if extent < 3 {
+            return Err("E36: Not enough room".into());
+        }
+        let new = self.windows.len();
+        self.windows.push(Window::new(self.buffer_index));
+        self.layout.split(self.window, new, vertical, extent);
+        let jumps = self.jumps.copy(&mut self.buffers);
+        self.windows[self.window].leave(self.buffer_index, &mut self.buffers[self.buffer_index].1, &mut self.jumps);
+        self.window = new;
+        self.jumps = jumps;
+        self.update_layout()?;
+        if !file.trim().is_empty() {
+            self.edit(file, false)?;
+        }
+        Ok(())
+    }
+
+    /// Closes window `index`, the previous one in `Ctrl-W w` order becoming
+    /// current if it was
+    fn close_window(&mut self, index: usize) -> Result<()> {
+        if self.windows.len() == 1 {
+            if self.tabs.is_empty() {
+                return Err("E444: Cannot close last window".into());
+            }
+            return self.close_tab("");
+        }
+        if index == self.window {
+            let order = self.layout.order();
+            let position = order.iter().position(|window| *window == index).unwrap();
+            self.go_to_window(order[if position > 0 { position - 1 } else { 1 }]);
+        }
+        self.windows[index].forget(&mut self.buffers);
+        self.windows.remove(index);
+        self.layout.close(index);
+        if self.window > index {
+            self.window -= 1;
+        }
+        self.update_layout()?;
+        Ok(())
+    }
+
+    /// `Ctrl-W o` and `:only`: closes every window but the current one
+    fn only_window(&mut self) -> Result<()> {
+        for (index, window) in self.windows.iter_mut().enumerate() {
+            if index != self.window {
+                window.forget(&mut self.buffers);
+            }
+        }
+        self.windows = vec![Window::new(self.buffer_index)];
+        self.window = 0;
+        self.layout.only();
+        self.update_layout()?;
+        Ok(())
+    }
+
+    /// Makes tab page `index`, counting the current one, the current tab
+    /// page
+    fn go_to_tab(&mut self, index: usize) {
+        if index == self.tab {
+            return;
+        }
+        self.windows[self.window].leave(self.buffer_index, &mut self.buffers[self.buffer_index].1, &mut self.jumps);
+        let current = TabPage {
+            windows: std::mem::take(&mut self.windows),
+            window: self.window,
+            layout: std::mem::take(&mut self.layout),
+        };
+        self.tabs.insert(self.tab, current);
+        let page = self.tabs.remove(index);
+        self.windows = page.windows;
+        self.window = page.window;
+        self.layout = page.layout;
+        self.tab = index;
+        self.buffer_index = self.windows[self.window].enter(&mut self.buffers, &mut self.jumps);
+    }
+
+    /// The buffers shown in tab page `index`, and the one of its current
+    /// window
+    fn tab_buffers(&self, index: usize) -> (Vec<usize>, usize) {
+        if index == self.tab {
+            return ((0..self.windows.len()).map(|window| self.window_buffer(window)).collect(), self.buffer_index);
+        }
+        let page = &self.tabs[if index < self.tab { index } else { index - 1 }];
+        let buffers = page.windows.iter().map(|window| window.buffer).collect();
+        (buffers, page.windows[page.window].buffer)
+    }
+
+    /// `:tabnew [file]`: opens a tab page after the current one, on a new
+    /// buffer or on `file`, its window starting with a copy of the jump list
+    fn new_tab(&mut self, file: &str) -> Result<()> {
+        let buffer = if file.trim().is_empty() {
+            self.buffers.push((String::new(), Buffer::new(&[])));
+            self.buffers.len() - 1
+        } else {
+            self.buffer_index
+        };
+        let page = TabPage {
+            windows: vec![Window::new(buffer)],
+            window: 0,
+            layout: Layout::default(),
+        };
+        self.tabs.insert(self.tab, page);
+        let jumps = self.jumps.copy(&mut self.buffers);
+        self.go_to_tab(self.tab + 1);
+        self.jumps = jumps;
+        if !file.trim().is_empty() {
+            self.edit(file, false)?;
+        }
+        self.update_layout()?;
+        Ok(())
+    }
+
+    /// `:tabclose [N]`: closes tab page `N`, or the current one, going to
+    /// the next tab page, or the previous one if it was the last
+    fn close_tab(&mut self, argument: &str) -> Result<()> {
+        if self.tabs.is_empty() {
+            return Err("E784: Cannot close last tab page".into());
+        }
+        let count = self.tabs.len() + 1;
+        let index = match argument.trim() {
+            "" => self.tab,
+            number => number
+                .parse::<usize>()
+                .ok()
+                .filter(|number| (1..=count).contains(number))
+                .ok_or_else(|| format!("E475: Invalid argument: {number}"))?
+                - 1,
+        };
+        if index == self.tab {
+            self.go_to_tab(if index + 1 < count { index + 1 } else { index - 1 });
+        }
+        let mut page = self.tabs.remove(if index < self.tab { index } else { index - 1 });
+        if index < self.tab {
+            self.tab -= 1;
+        }
+        for window in &mut page.windows {
+            window.forget(&mut self.buffers);
+        }
+        self.update_layout()?;
+        Ok(())
+    }
+
+    /// `gt` and `gT`: goes to the next tab page, or the previous one when
+    /// not `forward`, wrapping around. A count goes to that tab page with
+    /// `gt`, or that many back with `gT`.
+    fn next_tab(&mut self, count: Option<usize>, forward: bool) {
+        let total = self.tabs.len() + 1;
+        let index = match count {
+            Some(number) if forward => number.clamp(1, total) - 1,
+            _ if forward => (self.tab + 1) % total,
+            count => (self.tab + total - count.unwrap_or(1) % total) % total,
+        };
+        self.go_to_tab(index);
+    }
+
+    /// `:tabmove [N]`: puts the current tab page after tab page `N`, first
+    /// for 0 and last without one, or `N` places further with `+N` or `-N`
+    fn move_tab(&mut self, argument: &str) -> Result<()> {
+        let argument = argument.trim();
+        let invalid = || format!("E474: Invalid argument: {argument}");
+        let number = |digits: &str| match digits {
+            "" => Ok(1),
+            digits => digits.parse::<usize>().map_err(|_| invalid()),
+        };
+        let last = self.tabs.len();
+        let target = if let Some(digits) = argument.strip_prefix('+') {
+            Some(self.tab + number(digits)?).filter(|target| *target <= last)
+        } else if let Some(digits) = argument.strip_prefix('-') {
+            self.tab.checked_sub(number(digits)?)
+        } else {
+            match argument {
+                "" | "$" => Some(last),
+                digits => {
+                    let after = digits.parse::<usize>().map_err(|_| invalid())?.min(last + 1);
+                    Some(if after <= self.tab { after } else { after - 1 })
+                }
+            }
+        };
+        self.tab = target.ok_or_else(invalid)?;
+        Ok(())
+    }
+
+    /// The first row, listing the tab pages with the file of their current
+    /// window, their number of windows and whether one was modified
+    fn draw_tab_line(&self, width: usize) -> Result<()> {
+        let mut stdout = stdout();
+        queue!(stdout, MoveTo(0, 0))?;
+        let mut used = 0;
+        for index in 0..=self.tabs.len() {
+            let (buffers, current) = self.tab_buffers(index);
+            let mut flags = String::new();
+            if buffers.len() > 1 {
+                flags.push_str(&buffers.len().to_string());
+            }
+            if buffers.iter().any(|buffer| self.buffers[*buffer].1.is_modified()) {
+                flags.push('+');
+            }
+            if !flags.is_empty() {
+                flags.push(' ');
+            }
+            let label = format!(" {flags}{} ", display_name(&self.buffers[current].0));
+            let label: String = label.chars().take(width - used).collect();
+            used += label.chars().count();
+            let attribute = if index == self.tab { Attribute::Bold } else { Attribute::Reverse };
+            queue!(stdout, SetAttribute(attribute), Print(label), SetAttribute(Attribute::Reset))?;
+        }
+        let fill = " ".repeat(width - used);
+        queue!(stdout, SetAttribute(Attribute::Reverse), Print(fill), SetAttribute(Attribute::Reset))?;
+        Ok(())
+    }
+
+    /// `Ctrl-W` followed by `key`
+    fn window_command(&mut self, key: KeyEvent) -> Result<()> {
+        let count = self.count.take();
+        let event::KeyCode::Char(key) = key.code else {
+            return Ok(());
+        };
+        let delta = count.unwrap_or(1) as isize;
+        match key {
+            's' | 'S' => self.split_window(false, "")?,
+            'v' => self.split_window(true, "")?,
+            'c' => self.close_window(self.window)?,
+            'q' => self.execute_command("quit")?,
+            'o' => self.only_window()?,
+            'h' | 'j' | 'k' | 'l' => {
+                for _ in 0..delta {
+                    if let Some(index) = self.neighbour_window(key)? {
+                        self.go_to_window(index);
+                    }
+                }
+            }
+            'w' | 'W' => {
+                let order = self.layout.order();
+                let position = order.iter().position(|window| *window == self.window).unwrap();
+                let target = match count {
+                    Some(count) => order[count.min(order.len()) - 1],
+                    None if key == 'w' => order[(position + 1) % order.len()],
+                    None => order[(position + order.len() - 1) % order.len()],
+                };
+                self.go_to_window(target);
+            }
+            '=' => self.layout.equalize(),
+            '+' | '-' => self.layout.resize(self.window, false, if key == '+' { delta } else { -delta }),
+            '>' | '<' => self.layout.resize(self.window, true, if key == '>' { delta } else { -delta }),
+            _ => (),
+        }
+        self.update_layout()?;
+        Ok(())
+    }
+
+    /// The window next to the current one in the direction of `key`, one of
+    /// `hjkl`, level with the cursor
+    fn neighbour_window(&mut self, key: char) -> Result<Option<usize>> {
+        let areas = self.update_layout()?;
+        let area = areas[self.window];
+        let buffer = &self.buffers[self.buffer_index].1;
+        let (row, column) = buffer.wrapped_screen_position(self.text_width(), self.wrap().as_ref());
+        let column = buffer.gutter_width(&self.gutter()) + column;
+        let (row, column) = (area.top + row, area.left + column.min(area.width.saturating_sub(1)));
+        let target = match key {
+            'h' => area.left.checked_sub(1 + 1).map(|left| (row, left)),
+            'l' => Some((row, area.left + area.width + 1)),
+            'k' => area.top.checked_sub(1).map(|top| (top, column)),
+            _ => Some((area.top + area.height, column)),
+        };
+        Ok(target.and_then(|target| areas.iter().position(|area| area.contains(target))))
+    }
+
+    pub fn capture_keypress(&mut self) -> KeyEvent {
+        self.typeahead.read()
+    }
+
+    pub fn process_command(&mut self, command: String) {
+        if let Err(error) = self.execute_command(&command) {
+            self.error = Some(error.to_string());
+        }
+    }
+
+    fn execute_command(&mut self, input: &str) -> Result<()> {
+        let command = Command::parse(input)?;
+        self.run_command(&command, input)?;
+        match &command.next {
+            Some(next) => self.execute_command(next),
+            None => Ok(()),
+        }
+    }
+
+    fn run_command(&mut self, command: &Command, input: &str) -> Result<()> {
+        let argument = command.argument.as_str();
+        match command.name.as_str() {
+            "" => {
+                if let Some(range) = command.range(self)? {
+                    let buffer = &self.buffers[self.buffer_index].1;
+                    let position = (range.end, buffer.first_char_of(range.end));
+                    self.jump(self.here(), position)?;
+                }
+            }
+            "quit" if self.windows.len() > 1 || !self.tabs.is_empty() => self.close_window(self.window)?,
+            "quit" if !command.bang && self.buffers[self.buffer_index].1.is_modified() => {
+                return Err("E37: No write since last change (add ! to override)".into());
+            }
+            "quit" if !command.bang => {
+                if let Some((path, _)) = self.buffers.iter().find(|(_, buffer)| buffer.is_modified()) {
+                    let name = display_name(path);
+                    return Err(format!("E162: No write since last change for buffer \"{name}\"").into());
+                }
+                self.wants_out = true;
+            }
+            "quit" => self.wants_out = true,
+            "close" => self.close_window(self.window)?,
+            "only" => self.only_window()?,
+            "split" | "vsplit" => self.split_window(command.name == "vsplit", argument)?,
+            "tabnew" | "tabedit" => self.new_tab(argument)?,
+            "tabclose" => self.close_tab(argument)?,
+            "tabonly" => {
+                for mut page in self.tabs.drain(..) {
+                    for window in &mut page.windows {
+                        window.forget(&mut self.buffers);
+                    }
+                }
+                self.tab = 0;
+            }
+            "tabnext" | "tabprevious" => {
+                let count = match argument.trim() {
+                    "" => None,
+                    count => Some(count.parse::<usize>().map_err(|_| format!("E474: Invalid argument: {count}"))?),
+                };
+                self.next_tab(count, command.name == "tabnext");
+            }
+            "tabmove" => self.move_tab(argument)?,
+            "set" => {
+                for argument in options::arguments(argument) {
+                    match self.options.set(argument) {
+                        Ok(shown) => self.message = shown.or(self.message.take()),
+                        Err(error) => self.error = Some(error),
+                    }
+                }
+            }
+            "nohlsearch" => self.no_highlight = true,
+            "substitute" | "&" => self.substitute(command.range_or_current(self)?, argument)?,
+            "delete" | "yank" => {
+                let (register, count) = register_and_count(argument)?;
+                let range = with_count(command.range_or_current(self)?, count, self.last_line());
+                let buffer = &mut self.buffers[self.buffer_index].1;
+                let text = Register::linewise(buffer.lines(range));
+                let count = range.end - range.start + 1;
+                if command.name == "yank" {
+                    buffer.set_mark('[', (range.start, 0));
+                    buffer.set_mark(']', (range.end, 0));
+                    self.registers.yank(register, text);
+                    self.report_lines(count, "yanked");
+                } else {
+                    self.registers.delete(register, text);
+                    buffer.delete_lines(range);
+                    let line = buffer.cursor_position().0;
+                    self.cursor_to_line(line)?;
+                    self.report_lines(count, "fewer");
+                }
+            }
+            "move" | "copy" | "t" => {
+                let range = command.range_or_current(self)?;
+                let below = ex::destination(argument, self)?;
+                let buffer = &mut self.buffers[self.buffer_index].1;
+                let count = range.end - range.start + 1;
+                let last = if command.name == "move" {
+                    buffer.move_lines(range, below)?
+                } else {
+                    buffer.insert_lines(below, buffer.lines(range))
+                };
+                self.cursor_to_line(last)?;
+                self.report_lines(count, if command.name == "move" { "moved" } else { "more" });
+            }
+            "join" => {
+                let (_, count) = register_and_count(argument)?;
+                let mut range = command.range_or_current(self)?;
+                if let Some(count) = count {
+                    range = with_count(Range::line(range.end), Some(count.max(2)), self.last_line());
+                }
+                self.buffers[self.buffer_index].1.join_lines(range, !command.bang);
+                self.cursor_to_line(range.start)?;
+            }
+            ">" | "<" => {
+                let shifts = argument.chars().take_while(|c| c.to_string() == command.name).count();
+                let (_, count) = register_and_count(&argument[shifts..])?;
+                let range = with_count(command.range_or_current(self)?, count, self.last_line());
+                let amount = (shifts + 1) as isize * if command.name == ">" { 1 } else { -1 };
+                let (shiftwidth, expandtab) = (self.options.shiftwidth, self.options.expandtab);
+                self.buffers[self.buffer_index].1.shift_lines(range, amount, shiftwidth, expandtab);
+                self.cursor_to_line(range.end)?;
+                let count = range.end - range.start + 1;
+                if count > 2 {
+                    let times = if shifts == 0 { "1 time".to_string() } else { format!("{} times", shifts + 1) };
+                    self.message = Some(format!("{count} lines {}ed {times}", command.name));
+                }
+            }
+            "put" => {
+                let (register, _) = register_and_count(argument)?;
+                let name = register.unwrap_or('"');
+                let text = self
+                    .registers
+                    .get(name)
+                    .ok_or_else(|| format!("E353: Nothing in register {name}"))?
+                    .lines
+                    .clone();
+                let mut below = command.line_below(self)?;
+                if command.bang {
+                    below = below.and_then(|line| line.checked_sub(1));
+                }
+                let count = text.len();
+                let last = self.buffers[self.buffer_index].1.insert_lines(below, text);
+                self.cursor_to_line(last)?;
+                self.report_lines(count, "more");
+            }
+            "global" | "vglobal" => self.global(command)?,
+            "sort" => {
+                let range = command.range(self)?.unwrap_or(Range {
+                    start: 0,
+                    end: self.last_line(),
+                });
+                self.sort(range, Sort::parse(argument, command.bang)?)?;
+            }
+            "print" => {
+                let range = command.range_or_current(self)?;
+                let buffer = &self.buffers[self.buffer_index].1;
+                self.message = buffer.lines(Range::line(range.end)).pop();
+                self.cursor_to_line(range.end)?;
+            }
+            "!" => {
+                let typed = if command.bang { format!("!{argument}") } else { argument.to_string() };
+                let shell_command = self.expand_shell_command(&typed)?;
+                match command.range(self)? {
+                    Some(range) => self.filter_lines(range, &shell_command)?,
+                    None => shell::run(&shell_command, None, &mut self.typeahead)?,
+                }
+            }
+            "read" => {
+                let below = command.line_below(self)?;
+                let typed = if command.bang { Some(argument) } else { argument.strip_prefix('!') };
+                let lines = match typed {
+                    Some(typed) => {
+                        let shell_command = self.expand_shell_command(typed)?;
+                        shell::filter(&shell_command, None)?.0
+                    }
+                    None => {
+                        let path = self.file_argument(argument)?;
+                        let file = read(&path).map_err(|_| format!("E484: Can't open file {path}"))?;
+                        String::from_utf8_lossy(&file).lines().map(str::to_string).collect()
+                    }
+                };
+                if !lines.is_empty() {
+                    let first = below.map_or(0, |line| line + 1);
+                    self.buffers[self.buffer_index].1.insert_lines(below, lines);
+                    self.cursor_to_line(first)?;
+                }
+            }
+            "write" | "update" => {
+                let buffer = &self.buffers[self.buffer_index].1;
+                if command.name == "update" && !buffer.is_modified() {
+                    return Ok(());
+                }
+                let range = command.range(self)?;
+                let lines = buffer.lines(range.unwrap_or(Range {
+                    start: 0,
+                    end: buffer.line_count() - 1,
+                }));
+                match argument.strip_prefix('!') {
+                    Some(typed) => {
+                        let shell_command = self.expand_shell_command(typed)?;
+                        shell::run(&shell_command, Some(lines), &mut self.typeahead)?;
+                    }
+                    None => {
+                        let path = self.file_argument(argument)?;
+                        if range.is_some() && path == self.buffers[self.buffer_index].0 && !command.bang {
+                            return Err("E140: Use ! to write partial buffer".into());
+                        }
+                        let buffer = &self.buffers[self.buffer_index].1;
+                        if path == self.buffers[self.buffer_index].0 && buffer.is_readonly() && !command.bang {
+                            return Err("E45: 'readonly' option is set (add ! to override)".into());
+                        }
+                        let ending = buffer.line_ending();
+                        let text: String = lines.iter().map(|line| format!("{line}{ending}")).collect();
+                        std::fs::write(&path, &text).map_err(|error| format!("E212: Can't open file for writing: {path}: {error}"))?;
+                        if range.is_none() && path == self.buffers[self.buffer_index].0 {
+                            self.buffers[self.buffer_index].1.set_saved();
+                        }
+                        self.message = Some(format!("\"{path}\" {}L, {}B written", lines.len(), text.len()));
+                    }
+                }
+            }
+            "mark" => {
+                let line = command.range_or_current(self)?.end;
+                let mut names = argument.chars();
+                let name = names.next().ok_or("E471: Argument required")?;
+                if names.next().is_some() {
+                    return Err(format!("E488: Trailing characters: {argument}").into());
+                }
+                self.set_mark(name, (line, 0))?;
+            }
+            "marks" => self.list_marks(argument)?,
+            "sign" => self.sign(argument)?,
+            "messages" if argument == "clear" => self.messages.clear(),
+            "messages" => self.message = Some(self.messages.join("\n")).filter(|messages| !messages.is_empty()),
+            "jumps" => self.message = Some(self.jumps.list(&self.buffers, self.buffer_index).join("\n")),
+            "changes" => {
+                let (changes, current) = self.buffers[self.buffer_index].1.changes();
+                let mut lines = vec!["change line  col text".to_string()];
+                for (index, (line, column)) in changes.iter().enumerate() {
+                    let marker = if index == current { '>' } else { ' ' };
+                    let text = self.buffers[self.buffer_index].1.lines(Range::line(*line)).remove(0);
+                    let distance = index.abs_diff(current);
+                    lines.push(format!("{marker}{distance:>3} {:>5} {column:>4} {}", line + 1, text.trim_start()));
+                }
+                if current == changes.len() {
+                    lines.push(">".to_string());
+                }
+                self.message = Some(lines.join("\n"));
+            }
+            "edit" => self.edit(argument, command.bang)?,
+            "buffers" | "files" | "ls" => self.message = Some(self.list_buffers(command.bang)),
+            "buffer" => {
+                let index = self.find_buffer(argument)?;
+                self.edit_buffer(index)?;
+            }
+            "bnext" | "bprevious" | "bNext" => {
+                let count = match argument.trim() {
+                    "" => 1,
+                    count => count.parse::<isize>().map_err(|_| format!("E488: Trailing characters: {count}"))?,
+                };
+                self.cycle_buffers(if command.name == "bnext" { count } else { -count })?;
+            }
+            "bdelete" => self.delete_buffer(argument, command.bang)?,
+            "args" => {
+                if argument.trim().is_empty() {
+                    self.message = Some(self.args.list());
+                } else {
+                    self.args = ArgList::new(arglist::expand(argument));
+                    let path = self.args.go_to(0)?.to_string();
+                    self.edit(&path, command.bang)?;
+                }
+            }
+            "next" | "previous" | "Next" => {
+                let count = match argument.trim() {
+                    "" => 1,
+                    count => count.parse::<isize>().map_err(|_| format!("E488: Trailing characters: {count}"))?,
+                };
+                let path = self.args.go(if command.name == "next" { count } else { -count })?.to_string();
+                self.edit(&path, command.bang)?;
+            }
+            "first" | "rewind" | "last" => {
+                let index = if command.name == "last" { self.args.len().saturating_sub(1) } else { 0 };
+                let path = self.args.go_to(index)?.to_string();
+                self.edit(&path, command.bang)?;
+            }
+            "argdo" | "bufdo" => self.for_each_file(&command.name, argument)?,
+            "normal" => {
+                if argument.is_empty() {
+                    return Err("E471: Argument required".into());
+                }
+                let range = command.range(self)?;
+                self.normal(range, argument)?;
+            }
+            _ => return Err(format!("E492: Not an editor command: {}", input.trim()).into()),
+        }
+        Ok(())
+    }
+
+    /// Puts the cursor on the first non-blank of `line`, as most line
+    /// commands leave it
+    fn cursor_to_line(&mut self, line: usize) -> Result<()> {
+        let buffer = &mut self.buffers[self.buffer_index].1;
+        let line = line.min(buffer.line_count() - 1);
+        let position = (line, buffer.first_char_of(line));
+        buffer.move_to(position, self.area.height)
+    }
+
+    /// Tells how many lines a command changed, when there are enough of them
+    /// to be worth it
+    fn report_lines(&mut self, count: usize, what: &str) {
+        if count > 2 {
+            self.message = Some(format!("{count} {what} lines"));
+        }
+    }
+
+    /// The current buffer and the cursor position in it
+    fn here(&self) -> (usize, (usize, usize)) {
+        (self.buffer_index, self.buffers[self.buffer_index].1.cursor_position())
+    }
+
+    /// Moves the cursor to `target` after a jump from `from`, a buffer and
+    /// a position in it, remembering that for `''` and `Ctrl-O`
+    fn jump(&mut self, (buffer, position): (usize, (usize, usize)), target: (usize, usize)) -> Result<()> {
+        self.buffers[buffer].1.set_mark('\'', position);
+        self.jumps.record(&mut self.buffers, buffer, position);
+        self.buffers[self.buffer_index].1.move_to(target, self.area.height)
+    }
+
+    /// `Ctrl-O` and `Ctrl-I`: goes `count` entries back in the jump list,
+    /// or forward for a negative `count`
+    fn older_jump(&mut self, count: isize) -> Result<()> {
+        let here = self.here();
+        if let Some((buffer, position)) = self.jumps.go(&mut self.buffers, here, count) {
+            self.switch_buffer(buffer);
+            self.buffers[buffer].1.move_to(position, self.area.height)?;
+        }
+        Ok(())
+    }
+
+    /// Sets mark `name`, an uppercase one being taken from whatever buffer
+    /// had it
+    fn set_mark(&mut self, name: char, position: (usize, usize)) -> Result<()> {
+        if !Marks::is_valid(name) || "^.".contains(name) {
+            return Err("E191: Argument must be a letter or forward/backward quote".into());
+        }
+        if name.is_ascii_uppercase() {
+            for (_, buffer) in &mut self.buffers {
+                buffer.remove_mark(name);
+            }
+        }
+        self.buffers[self.buffer_index].1.set_mark(name, position);
+        Ok(())
+    }
+
+    /// Where mark `name` is, switching to the buffer that has it for an
+    /// uppercase mark set in another file
+    fn find_mark(&mut self, name: char) -> Result<(usize, usize)> {
+        if !Marks::is_valid(name) {
+            return Err("E78: Unknown mark".into());
+        }
+        if name.is_ascii_uppercase() {
+            if let Some(index) = self.buffers.iter().position(|(_, buffer)| buffer.mark(name).is_some()) {
+                self.switch_buffer(index);
+            }
+        }
+        Ok(self.buffers[self.buffer_index].1.mark(name).ok_or("E20: Mark not set")?)
+    }
+
+    /// Makes buffer `index` the current one, the one it replaces becoming
+    /// the alternate buffer
+    fn switch_buffer(&mut self, index: usize) {
+        if index != self.buffer_index {
+            self.alternate = Some(self.buffer_index);
+            self.buffer_index = index;
+        }
+        self.buffers[index].1.set_listed(true);
+    }
+
+    /// Goes to buffer `index` as `:buffer` does: a jump, telling about the
+    /// file
+    fn edit_buffer(&mut self, index: usize) -> Result<()> {
+        if index != self.buffer_index {
+            let (buffer, position) = self.here();
+            self.buffers[buffer].1.set_mark('\'', position);
+            self.jumps.record(&mut self.buffers, buffer, position);
+            self.switch_buffer(index);
+        }
+        let buffer = &mut self.buffers[index].1;
+        let position = buffer.cursor_position();
+        buffer.move_to(position, self.area.height)?;
+        self.message = Some(self.file_info());
+        Ok(())
+    }
+
+    /// The name of the current file, whether it was modified and where the
+    /// cursor is in it
+    fn file_info(&self) -> String {
+        let (path, buffer) = &self.buffers[self.buffer_index];
+        let modified = if buffer.is_modified() { " [Modified]" } else { "" };
+        let count = buffer.line_count();
+        let lines = if count == 1 { "line" } else { "lines" };
+        let percent = (buffer.cursor_position().0 + 1) * 100 / count;
+        format!("\"{}\"{modified} {count} {lines} --{percent}%--", display_name(path))
+    }
+
+    /// `:e[!] [file]`: edits `file`, in the buffer it already has if any, or
+    /// reloads the current one, which `!` allows when it was modified
+    fn edit(&mut self, argument: &str, bang: bool) -> Result<()> {
+        let path = match argument.trim() {
+            "" => {
+                let (path, buffer) = &self.buffers[self.buffer_index];
+                if buffer.is_modified() && !bang {
+                    return Err("E37: No write since last change (add ! to override)".into());
+                }
+                if path.is_empty() {
+                    return Err("E32: No file name".into());
+                }
+                let position = buffer.cursor_position();
+                let (mut buffer, message) = load(path);
+                buffer.move_to(position, self.area.height)?;
+                self.buffers[self.buffer_index].1 = buffer;
+                self.message = Some(message);
+                return Ok(());
+            }
+            "#" => self.buffers[self.alternate.ok_or("E23: No alternate file")?].0.clone(),
+            path => path.to_string(),
+        };
+        match self.buffers.iter().position(|(other, _)| *other == path) {
+            Some(index) => self.edit_buffer(index),
+            None => {
+                let (buffer, message) = load(&path);
+                self.buffers.push((path, buffer));
+                self.edit_buffer(self.buffers.len() - 1)?;
+                self.message = Some(message);
+                Ok(())
+            }
+        }
+    }
+
+    /// `:ls`: the listed buffers, or all of them with `!`, each with its
+    /// flags: `u` unlisted, `%` current, `#` alternate, `a` shown, `h`
+    /// hidden and `+` modified
+    fn list_buffers(&self, all: bool) -> String {
+        let mut lines = Vec::new();
+        for (index, (path, buffer)) in self.buffers.iter().enumerate() {
+            if !buffer.is_listed() && !all {
+                continue;
+            }
+            let unlisted = if buffer.is_listed() { ' ' } else { 'u' };
+            let current = if index == self.buffer_index {
+                '%'
+            } else if Some(index) == self.alternate {
+                '#'
+            } else {
+                ' '
+            };
+            let shown = (0..self.windows.len()).any(|window| self.window_buffer(window) == index);
+            let shown = if shown { 'a' } else { 'h' };
+            let modified = if buffer.is_modified() { '+' } else { ' ' };
+            let entry = format!("{:>3}{unlisted}{current}{shown} {modified} \"{}\"", index + 1, display_name(path));
+            lines.push(format!("{entry:<39} line {}", buffer.cursor_position().0 + 1));
+        }
+        lines.join("\n")
+    }
+
+    /// The buffer a `:buffer` argument names: a number, `%`, `#`, a file
+    /// name or a unique part of one; the current buffer when empty
+    fn find_buffer(&self, argument: &str) -> Result<usize> {
+        let argument = argument.trim();
+        match argument {
+            "" | "%" => return Ok(self.buffer_index),
+            "#" => return Ok(self.alternate.ok_or("E23: No alternate file")?),
+            _ => (),
+        }
+        if let Ok(number) = argument.parse::<usize>() {
+            if number == 0 || number > self.buffers.len() {
+                return Err(format!("E86: Buffer {number} does not exist").into());
+            }
+            return Ok(number - 1);
+        }
+        if let Some(index) = self.buffers.iter().position(|(path, _)| path == argument) {
+            return Ok(index);
+        }
+        let mut matches = self
+            .buffers
+            .iter()
+            .enumerate()
+            .filter(|(_, (path, buffer))| buffer.is_listed() && path.contains(argument))
+            .map(|(index, _)| index);
+        match (matches.next(), matches.next()) {
+            (Some(index), None) => Ok(index),
+            (Some(_), Some(_)) => Err(format!("E93: More than one match for {argument}").into()),
+            (None, _) => Err(format!("E94: No matching buffer for {argument}").into()),
+        }
+    }
+
+    /// `:bnext` and `:bprevious`: goes `count` listed buffers forward, or
+    /// back for a negative `count`, wrapping around the list
+    fn cycle_buffers(&mut self, count: isize) -> Result<()> {
+        let length = self.buffers.len() as isize;
+        let mut index = self.buffer_index;
+        for _ in 0..count.unsigned_abs() {
+            loop {
+                index = (index as isize + count.signum()).rem_euclid(length) as usize;
+                if self.buffers[index].1.is_listed() || index == self.buffer_index {
+                    break;
+                }
+            }
+        }
+        self.edit_buffer(index)
+    }
+
+    /// `:bd[!] [buffer]`: takes a buffer off the list, closing the other
+    /// windows showing it and going to another buffer if it is the current
+    /// one. A modified buffer needs `!`, and its changes are lost.
+    fn delete_buffer(&mut self, argument: &str, bang: bool) -> Result<()> {
+        let index = self.find_buffer(argument)?;
+        let (path, buffer) = &self.buffers[index];
+        if buffer.is_modified() {
+            if !bang {
+                let number = index + 1;
+                return Err(format!("E89: No write since last change for buffer {number} (add ! to override)").into());
+            }
+            self.buffers[index].1 = load(&path.clone()).0;
+        }
+        self.buffers[index].1.set_listed(false);
+        for window in (0..self.windows.len()).rev() {
+            if window != self.window && self.windows[window].buffer == index {
+                self.close_window(window)?;
+            }
+        }
+        if index == self.buffer_index {
+            let length = self.buffers.len();
+            let listed = |other: &usize| *other != index && self.buffers[*other].1.is_listed();
+            let next = self
+                .alternate
+                .filter(listed)
+                .or_else(|| (1..length).map(|offset| (index + offset) % length).find(listed));
+            self.buffer_index = next.unwrap_or_else(|| {
+                self.buffers.push((String::new(), Buffer::new(&[])));
+                length
+            });
+            let buffer = &mut self.buffers[self.buffer_index].1;
+            let position = buffer.cursor_position();
+            buffer.move_to(position, self.area.height)?;
+        }
+        if self.alternate.is_some_and(|alternate| alternate == index || alternate == self.buffer_index) {
+            self.alternate = None;
+        }
+        Ok(())
+    }
+
+    /// `:argdo` and `:bufdo`: runs `to_run` in every file of the argument
+    /// list, or in every listed buffer, stopping at the first error
+    fn for_each_file(&mut self, name: &str, to_run: &str) -> Result<()> {
+        if name == "argdo" {
+            for index in 0..self.args.len() {
+                let path = self.args.go_to(index)?.to_string();
+                self.edit(&path, false)?;
+                self.execute_command(to_run)?;
+            }
+        } else {
+            for index in 0..self.buffers.len() {
+                if self.buffers[index].1.is_listed() {
+                    self.edit_buffer(index)?;
+                    self.execute_command(to_run)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// `:sign define`, `undefine`, `list`, `place` and `unplace`: names the
+    /// text of a sign and puts it in the sign column of a buffer's lines
+    fn sign(&mut self, argument: &str) -> Result<()> {
+        let mut words = argument.split_whitespace();
+        let subcommand = words.next().unwrap_or("list");
+        let mut id = None;
+        let mut name = None;
+        let mut attributes = Vec::new();
+        for word in words {
+            match word.split_once('=') {
+                Some(attribute) => attributes.push(attribute),
+                None if subcommand.ends_with("define") || subcommand == "list" => name = Some(word),
+                None => id = Some(word),
+            }
+        }
+        let definition = |name: &str| self.sign_definitions.iter().position(|(defined, _)| defined == name);
+        match subcommand {
+            "define" => {
+                let name = name.ok_or("E156: Missing sign name")?;
+                let mut text = String::new();
+                for (key, value) in attributes {
+                    match key {
+                        "text" if (1..=SIGN_WIDTH).contains(&sign_width(value)) => text = value.to_string(),
+                        "text" => return Err(format!("E239: Invalid sign text: {value}").into()),
+                        _ => return Err(format!("E475: Invalid argument: {key}={value}").into()),
+                    }
+                }
+                match definition(name) {
+                    Some(index) => self.sign_definitions[index].1 = text,
+                    None => self.sign_definitions.push((name.to_string(), text)),
+                }
+            }
+            "undefine" => {
+                let name = name.ok_or("E156: Missing sign name")?;
+                let index = definition(name).ok_or_else(|| format!("E155: Unknown sign: {name}"))?;
+                self.sign_definitions.remove(index);
+            }
+            "list" => {
+                let listed: Vec<String> = self
+                    .sign_definitions
+                    .iter()
+                    .filter(|(defined, _)| name.is_none_or(|name| name == defined))
+                    .map(|(name, text)| format!("sign {name} text={text}"))
+                    .collect();
+                if let (Some(name), true) = (name, listed.is_empty()) {
+                    return Err(format!("E155: Unknown sign: {name}").into());
+                }
+                self.message = Some(listed.join("\n")).filter(|listed| !listed.is_empty());
+            }
+            "place" | "unplace" => {
+                let mut buffer = None;
+                let mut line = None;
+                let mut sign = None;
+                for (key, value) in attributes {
+                    match key {
+                        "buffer" | "file" => buffer = Some(self.find_buffer(value)?),
+                        "line" => line = Some(value.parse::<usize>().map_err(|_| format!("E474: Invalid argument: {value}"))?),
+                        "name" => sign = Some(value),
+                        _ => return Err(format!("E475: Invalid argument: {key}={value}").into()),
+                    }
+                }
+                if subcommand == "unplace" {
+                    return self.unplace_signs(id, buffer);
+                }
+                let Some(id) = id else {
+                    self.message = Some(self.list_signs(buffer));
+                    return Ok(());
+                };
+                let id = id.parse::<usize>().map_err(|_| format!("E474: Invalid argument: {id}"))?;
+                let sign = sign.ok_or("E156: Missing sign name")?;
+                let index = definition(sign).ok_or_else(|| format!("E155: Unknown sign: {sign}"))?;
+                let text = self.sign_definitions[index].1.clone();
+                let buffer = &mut self.buffers[buffer.unwrap_or(self.buffer_index)].1;
+                let placed = buffer.signs().into_iter().find(|(_, placed)| placed.id == id).map(|(line, _)| line);
+                let line = match (line, placed) {
+                    (Some(line), _) => line.saturating_sub(1).min(buffer.line_count() - 1),
+                    (None, Some(line)) => line,
+                    (None, None) => return Err(format!("E474: Invalid argument: {argument}").into()),
+                };
+                buffer.place_sign(id, line, sign, &text);
+            }
+            _ => return Err(format!("E160: Unknown sign command: {subcommand}").into()),
+        }
+        Ok(())
+    }
+
+    /// `:sign unplace`: takes away sign `id`, every sign for `*`, or the
+    /// sign on the cursor line, in `buffer` or else in the current one
+    fn unplace_signs(&mut self, id: Option<&str>, buffer: Option<usize>) -> Result<()> {
+        match id {
+            Some("*") => {
+                let buffers = self.buffers.iter_mut().enumerate();
+                for (_, (_, each)) in buffers.filter(|(index, _)| buffer.is_none_or(|buffer| buffer == *index)) {
+                    each.unplace_signs();
+                }
+            }
+            Some(id) => {
+                let id = id.parse::<usize>().map_err(|_| format!("E474: Invalid argument: {id}"))?;
+                let buffer = &mut self.buffers[buffer.unwrap_or(self.buffer_index)].1;
+                if !buffer.unplace_sign(id) {
+                    return Err(format!("E158: Invalid sign ID: {id}").into());
+                }
+            }
+            None => {
+                let buffer = &mut self.buffers[self.buffer_index].1;
+                let line = buffer.cursor_position().0;
+                let placed = buffer.signs().into_iter().find(|(at, _)| *at == line).map(|(_, sign)| sign.id);
+                if let Some(id) = placed {
+                    buffer.unplace_sign(id);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// What `:sign place` lists: the signs placed in `buffer`, or in every
+    /// buffer
+    fn list_signs(&self, buffer: Option<usize>) -> String {
+        let mut listed = vec!["--- Signs ---".to_string()];
+        for (index, (path, each)) in self.buffers.iter().enumerate() {
+            let signs = each.signs();
+            if buffer.map_or(signs.is_empty(), |buffer| buffer != index) {
+                continue;
+            }
+            listed.push(format!("Signs for {}:", display_name(path)));
+            for (line, sign) in signs {
+                listed.push(format!("    line={}  id={}  name={}", line + 1, sign.id, sign.name));
+            }
+        }
+        listed.join("\n")
+    }
+
+    /// `:marks [names]`: lists the marks set, with the text of their line
+    /// or, for an uppercase mark in another buffer, its file
+    fn list_marks(&mut self, argument: &str) -> Result<()> {
+        let buffer = &self.buffers[self.buffer_index].1;
+        let mut listed = Vec::new();
+        for name in marks::ORDER.chars().filter(|name| argument.is_empty() || argument.contains(*name)) {
+            let found = match buffer.mark(name) {
+                Some((line, column)) => Some((line, column, buffer.lines(Range::line(line)).remove(0))),
+                None if name.is_ascii_uppercase() => self
+                    .buffers
+                    .iter()
+                    .find_map(|(path, other)| other.mark(name).map(|(line, column)| (line, column, path.clone()))),
+                None => None,
+            };
+            if let Some((line, column, text)) = found {
+                listed.push(format!(" {name} {:>6} {column:>4} {}", line + 1, text.trim_start()));
+            }
+        }
+        if listed.is_empty() && !argument.is_empty() {
+            return Err(format!("E283: No marks matching \"{argument}\"").into());
+        }
+        listed.insert(0, "mark line  col file/text".to_string());
+        self.message = Some(listed.join("\n"));
+        Ok(())
+    }
+
+    /// `:[range]normal {keys}`: replays `keys` as typed in Command mode,
+    /// once on each line of the range
+    fn normal(&mut self, range: Option<Range>, keys: &str) -> Result<()> {
+        let keys = input::parse_keys(keys);
+        let lines = range.unwrap_or(Range::line(self.current_line()));
+        for line in lines.start..=lines.end {
+            if line >= self.buffers[self.buffer_index].1.line_count() || self.wants_out {
+                break;
+            }
+            if range.is_some() {
+                self.buffers[self.buffer_index].1.move_to((line, 0), self.area.height)?;
+            }
+            let waiting = self.typeahead.pending();
+            let was_replaying = self.typeahead.replay(keys.clone());
+            let mut result = Ok(());
+            while result.is_ok() && self.typeahead.pending() > waiting && !self.wants_out {
+                let keypress = self.capture_keypress();
+                result = self.process_keypress(keypress);
+            }
+            if result.is_ok() && matches!(self.mode, OperationMode::Insert | OperationMode::Replace) {
+                result = self.process_keypress(KeyEvent::new(event::KeyCode::Esc, KeyModifiers::NONE));
+            }
+            self.typeahead.stop_replaying(was_replaying);
+            result?;
+        }
+        Ok(())
+    }
+
+    /// `:[range]g/pattern/command` and `:v`: marks the lines that match (or
+    /// don't) and then runs the command on each of those still there
+    fn global(&mut self, command: &Command) -> Result<()> {
+        if self.in_global {
+            return Err("E147: Cannot do :global recursive".into());
+        }
+        let range = command.range(self)?.unwrap_or(Range {
+            start: 0,
+            end: self.last_line(),
+        });
+        let argument = command.argument.as_str();
+        let delimiter = match argument.chars().next() {
+            None => return Err("E35: No previous regular expression".into()),
+            Some(c) if c.is_alphanumeric() || "\\\"|".contains(c) => {
+                return Err("E146: Regular expressions can't be delimited by letters".into());
+            }
+            Some(c) => c,
+        };
+        let (mut pattern, to_run) = ex::split_pattern(&argument[delimiter.len_utf8()..], delimiter);
+        if pattern.is_empty() {
+            let last = self.last_search.as_ref().ok_or("E35: No previous regular expression")?;
+            pattern = last.pattern.clone();
+        }
+        let regex = regex::Regex::new(&pattern)?;
+        self.last_search = Some(Search {
+            pattern: pattern.clone(),
+            direction: Direction::Forward,
+            offset: Default::default(),
+        });
+        self.no_highlight = false;
+        let invert = command.bang || command.name == "vglobal";
+        let buffer = &mut self.buffers[self.buffer_index].1;
+        if buffer.mark_lines(&regex, range, invert) == 0 {
+            buffer.clear_marked();
+            self.message = Some(if invert {
+                format!("Pattern found in every line: {pattern}")
+            } else {
+                format!("Pattern not found: {pattern}")
+            });
+            return Ok(());
+        }
+        let to_run = if to_run.trim().is_empty() { "p" } else { to_run };
+        self.in_global = true;
+        let mut result = Ok(());
+        while let Some(line) = self.buffers[self.buffer_index].1.take_marked() {
+            let rows = self.area.height;
+            self.buffers[self.buffer_index].1.move_to((line, 0), rows)?;
+            result = self.execute_command(to_run);
+            if result.is_err() || self.wants_out {
+                break;
+            }
+        }
+        self.buffers[self.buffer_index].1.clear_marked();
+        self.in_global = false;
+        result
+    }
+
+    /// Replaces unescaped `!` in a shell command with the previous command
+    /// and `%` with the file name, remembering the result for the next `!`
+    fn expand_shell_command(&mut self, typed: &str) -> Result<String> {
+        let mut expanded = String::new();
+        let mut chars = typed.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => match chars.next() {
+                    Some(next @ ('!' | '%')) => expanded.push(next),
+                    Some(next) => {
+                        expanded.push(c);
+                        expanded.push(next);
+                    }
+                    None => expanded.push(c),
+                },
+                '!' => expanded.push_str(self.last_shell_command.as_ref().ok_or("E34: No previous command")?),
+                '%' => {
+                    let path = &self.buffers[self.buffer_index].0;
+                    if path.is_empty() {
+                        return Err("E499: Empty file name for '%'".into());
+                    }
+                    expanded.push_str(path);
+                }
+                c => expanded.push(c),
+            }
+        }
+        self.last_shell_command = Some(expanded.clone());
+        Ok(expanded)
+    }
+
+    /// The file named by a command argument, the current one when empty
+    fn file_argument(&self, argument: &str) -> Result<String> {
+        let path = match argument.trim() {
+            "" => &self.buffers[self.buffer_index].0,
+            path => path,
+        };
+        if path.is_empty() {
+            return Err("E32: No file name".into());
+        }
+        Ok(path.to_string())
+    }
+
+    /// `:{range}!command`: replaces the lines with what the command prints
+    /// when given them as input
+    fn filter_lines(&mut self, range: Range, shell_command: &str) -> Result<()> {
+        let buffer = &mut self.buffers[self.buffer_index].1;
+        let (output, status) = shell::filter(shell_command, Some(buffer.lines(range)))?;
+        let count = range.end - range.start + 1;
+        let whole = count == buffer.line_count();
+        buffer.delete_lines(range);
+        if !output.is_empty() {
+            let last = buffer.insert_lines(range.start.checked_sub(1), output);
+            if whole {
+                // the empty line left by deleting everything
+                buffer.delete_lines(Range::line(last + 1));
+            }
+        }
+        self.cursor_to_line(range.start)?;
+        if let Some(code) = status.code().filter(|code| *code != 0) {
+            self.message = Some(format!("shell returned {code}"));
+        } else if count > 2 {
+            self.message = Some(format!("{count} lines filtered"));
+        }
+        Ok(())
+    }
+
+    /// `:[range]sort[!] [nxfbo] [i] [u] [/pattern/ [r]]`
+    fn sort(&mut self, range: Range, mut sort: Sort) -> Result<()> {
+        if sort.pattern.as_deref() == Some("") {
+            let last = self.last_search.as_ref().ok_or("E35: No previous regular expression")?;
+            sort.pattern = Some(last.pattern.clone());
+        }
+        let regex = match &sort.pattern {
+            Some(pattern) => {
+                self.last_search = Some(Search {
+                    pattern: pattern.clone(),
+                    direction: Direction::Forward,
+                    offset: Default::default(),
+                });
+                Some(regex::Regex::new(pattern)?)
+            }
+            None => None,
+        };
+        let removed = self.buffers[self.buffer_index].1.sort(range, &sort, regex.as_ref());
+        self.cursor_to_line(range.start)?;
+        self.report_lines(removed, "fewer");
+        Ok(())
+    }
+
+    /// `:[range]s/pattern/replacement/[flags] [count]` and its repeats
+    fn substitute(&mut self, mut range: Range, argument: &str) -> Result<()> {
+        let (mut substitute, count) = Substitute::parse(argument, self.last_substitute.as_ref())?;
+        if substitute.pattern.is_empty() {
+            let last = self.last_search.as_ref().ok_or("E35: No previous regular expression")?;
+            substitute.pattern = last.pattern.clone();
+        }
+        let regex = substitute.regex()?;
+        let area = self.area;
+        let gutter = self.gutter();
+        let (width, sidescroll, wrap) = (self.text_width(), self.sidescroll(), self.wrap());
+        let buffer = &mut self.buffers[self.buffer_index].1;
+        if let Some(count) = count {
+            range = Range {
+                start: range.end,
+                end: (range.end + count - 1).min(buffer.line_count() - 1),
+            };
+        }
+        let typeahead = &mut self.typeahead;
+        let result = buffer.substitute(&regex, &substitute, range, |buffer, found| {
+            buffer.move_to(found.start, area.height)?;
+            buffer.scroll_to_cursor((area.height, width), wrap.as_ref());
+            if wrap.is_none() {
+                buffer.scroll_sideways(width, sidescroll);
+            }
+            confirm_substitution(buffer, found, area, (&gutter, wrap.as_ref()), &substitute.replacement, typeahead)
+        })?;
+        self.last_search = Some(Search {
+            pattern: substitute.pattern.clone(),
+            direction: Direction::Forward,
+            offset: Default::default(),
+        });
+        self.no_highlight = false;
+        let flags = substitute.flags;
+        let pattern = substitute.pattern.clone();
+        self.last_substitute = Some(substitute);
+        let Some(last_line) = result.last_line else {
+            if flags.ignore_errors {
+                return Ok(());
+            }
+            return Err(format!("E486: Pattern not found: {pattern}").into());
+        };
+        if !flags.report_only {
+            let position = (last_line, buffer.first_char_of(last_line));
+            buffer.move_to(position, area.height)?;
+        }
+        let plural = |count: usize, one: &str, many: &str| {
+            format!("{count} {}", if count == 1 { one } else { many })
+        };
+        let lines = plural(result.lines, "line", "lines");
+        if flags.report_only {
+            let matches = plural(result.substitutions, "match", "matches");
+            self.message = Some(format!("{matches} on {lines}"));
+        } else if result.substitutions > 2 {
+            let substitutions = plural(result.substitutions, "substitution", "substitutions");
+            self.message = Some(format!("{substitutions} on {lines}"));
+        }
+        Ok(())
+    }
 
-impl Editor {
-    pub fn new() -> Self {
-        Self {
-            wants_out: false,
-            mode: OperationMode::default(),
-            buffers: Vec::new(),
-            buffer_index: 0,
-            last_line: CommandLine { history: Vec::new() }
+    /// `zh`, `zl`, `zs` and `ze`: scrolls the view sideways
+    fn z_command(&mut self) {
+        let key = self.capture_keypress().code;
+        let count = self.count.take().unwrap_or(1) as isize;
+        let width = self.text_width();
+        let sidescrolloff = self.options.sidescrolloff;
+        let buffer = &mut self.buffers[self.buffer_index].1;
+        match key {
+            event::KeyCode::Char('l') | event::KeyCode::Right => buffer.scroll_columns(count, width, sidescrolloff),
+            event::KeyCode::Char('h') | event::KeyCode::Left => buffer.scroll_columns(-count, width, sidescrolloff),
+            event::KeyCode::Char('s') => buffer.scroll_cursor_to_edge(true, width, sidescrolloff),
+            event::KeyCode::Char('e') => buffer.scroll_cursor_to_edge(false, width, sidescrolloff),
+            _ => (),
         }
     }
 
-    pub fn run(&mut self) -> Result<()> {
-        while !self.wants_out {
-            let current_buffer = &mut self.buffers[self.buffer_index].1;
-            let (_, mut rows) = size()?;
+    /// Commands starting with `g`
+    fn g_command(&mut self) -> Result<()> {
+        let keypress = self.capture_keypress();
+        match keypress.code {
+            event::KeyCode::Char('&') => self.process_command("%s//~/&".to_string()),
+            event::KeyCode::Char('J') => self.join(false)?,
+            event::KeyCode::Char(key @ ('j' | 'k')) => {
+                let count = self.count.take().unwrap_or(1) as isize;
+                let (width, wrap) = (self.text_width(), self.wrap());
+                let buffer = &mut self.buffers[self.buffer_index].1;
+                let position = buffer.screen_line_motion(if key == 'j' { count } else { -count }, width, wrap.as_ref());
+                buffer.move_to(position, self.area.height)?;
+            }
+            event::KeyCode::Char(key @ ('0' | '$')) => {
+                let (width, wrap) = (self.text_width(), self.wrap());
+                let buffer = &mut self.buffers[self.buffer_index].1;
+                let position = buffer.screen_line_edge(key == '$', width, wrap.as_ref());
+                buffer.move_to(position, self.area.height)?;
+            }
+            event::KeyCode::Char(key @ ('t' | 'T')) => {
+                let count = self.count.take();
+                self.next_tab(count, key == 't');
+            }
+            event::KeyCode::Char(key @ ('a' | 'x'))
+                if keypress.modifiers.contains(KeyModifiers::CONTROL) && matches!(self.mode, OperationMode::Visual(_)) =>
+            {
+                self.visual_increment(if key == 'a' { 1 } else { -1 }, true)?;
+            }
+            event::KeyCode::Char(key @ (';' | ',')) => {
+                let count = self.count.take().unwrap_or(1) as isize;
+                let buffer = &mut self.buffers[self.buffer_index].1;
+                match buffer.older_change(if key == ';' { count } else { -count }) {
+                    Ok(position) => buffer.move_to(position, self.area.height)?,
+                    Err(error) => self.error = Some(error.to_string()),
+                }
+            }
+            event::KeyCode::Char('v') => {
+                let buffer = &mut self.buffers[self.buffer_index].1;
+                let marks = (buffer.mark('<'), buffer.mark('>'));
+                let (Some(selection), (Some(start), Some(end))) = (self.last_visual, marks) else {
+                    return Ok(());
+                };
+                let (anchor, cursor) = if selection.anchor <= selection.cursor { (start, end) } else { (end, start) };
+                self.visual_anchor = anchor;
+                self.visual_to_end = selection.to_end;
+                self.mode = OperationMode::Visual(selection.kind);
+                buffer.move_to(cursor, self.area.height)?;
+            }
+            _ => (),
+        }
+        Ok(())
+    }
 
-            rows -= 1;
-            current_buffer.draw_rows(rows.into())?;
-            let keypress = self.capture_keypress();
-            self.process_keypress(keypress)?;
+    /// The current Visual mode selection, if any
+    fn selection(&self) -> Option<Selection> {
+        let OperationMode::Visual(kind) = self.mode else {
+            return None;
+        };
+        Some(Selection {
+            kind,
+            anchor: self.visual_anchor,
+            cursor: self.buffers[self.buffer_index].1.cursor_position(),
+            to_end: self.visual_to_end,
+        })
+    }
+
+    fn start_visual(&mut self, kind: SelectionKind) {
+        self.visual_anchor = self.buffers[self.buffer_index].1.cursor_position();
+        self.visual_to_end = false;
+        self.mode = OperationMode::Visual(kind);
+    }
+
+    fn exit_visual(&mut self) {
+        self.last_visual = self.selection();
+        if let Some(selection) = self.last_visual {
+            let (start, end) = selection.bounds();
+            let buffer = &mut self.buffers[self.buffer_index].1;
+            buffer.set_mark('<', start);
+            buffer.set_mark('>', end);
+        }
+        self.mode = OperationMode::Command;
+    }
+
+    /// Keys with a meaning of their own in Visual mode, returning whether
+    /// `keypress` was one of them
+    fn process_visual_keypress(&mut self, keypress: KeyEvent, kind: SelectionKind) -> Result<bool> {
+        let control = keypress.modifiers.contains(KeyModifiers::CONTROL);
+        let key = match keypress.code {
+            event::KeyCode::Esc => 'c',
+            event::KeyCode::Char(key) => key,
+            _ => return Ok(false),
+        };
+        let switch_to = |new: SelectionKind| {
+            if kind == new {
+                OperationMode::Command
+            } else {
+                OperationMode::Visual(new)
+            }
+        };
+        match key {
+            'c' if control || keypress.code == event::KeyCode::Esc => self.exit_visual(),
+            'v' | 'V' => {
+                let mode = match key {
+                    'v' if control => switch_to(SelectionKind::Block),
+                    'v' => switch_to(SelectionKind::Chars),
+                    _ => switch_to(SelectionKind::Lines),
+                };
+                if mode == OperationMode::Command {
+                    self.exit_visual();
+                }
+                self.mode = mode;
+            }
+            'I' | 'A' if kind == SelectionKind::Block => self.block_insert(key)?,
+            'a' | 'x' if control => self.visual_increment(if key == 'a' { 1 } else { -1 }, false)?,
+            'o' | 'O' => {
+                let buffer = &mut self.buffers[self.buffer_index].1;
+                let cursor = buffer.cursor_position();
+                let (anchor, target) = if key == 'O' && kind == SelectionKind::Block {
+                    ((self.visual_anchor.0, cursor.1), (cursor.0, self.visual_anchor.1))
+                } else {
+                    (cursor, self.visual_anchor)
+                };
+                self.visual_anchor = anchor;
+                buffer.move_to(target, self.area.height)?;
+            }
+            'd' | 'x' | 'X' | 'D' | 'y' | 'Y' | 'c' | 's' | 'C' | 'S' | '>' | '<' | '!' | ':' | 'J' | '~' | 'u'
+            | 'U'
+                if !control =>
+            {
+                self.visual_operator(key)?
+            }
+            _ => return Ok(false),
         }
+        Ok(true)
+    }
+
+    /// Visual block `I` and `A`: starts Insert mode on the first line of the
+    /// block, the text typed going to the other lines once it ends
+    fn block_insert(&mut self, key: char) -> Result<()> {
+        let Some(selection) = self.selection() else {
+            return Ok(());
+        };
+        self.exit_visual();
+        let rows = self.area.height;
+        let range = selection.lines();
+        let buffer = &mut self.buffers[self.buffer_index].1;
+        let (left, right) = selection.columns(buffer);
+        let (column, pad) = match key {
+            'I' => (Some(left), false),
+            _ if selection.to_end => (None, false),
+            _ => (Some(right + 1), true),
+        };
+        let lines: Vec<usize> = (range.start + 1..=range.end)
+            .filter(|line| pad || column.is_none() || buffer.line_width(*line) > left)
+            .collect();
+        let x = match column {
+            Some(column) => {
+                buffer.insert_at_column(range.start, Some(column), "", pad);
+                buffer.grapheme_at_column(range.start, column)
+            }
+            None => usize::MAX,
+        };
+        buffer.move_to_insert((range.start, x), rows)?;
+        self.block_insert = Some(BlockInsert::new(buffer, lines, column, pad));
+        self.mode = OperationMode::Insert;
         Ok(())
     }
 
-    pub fn capture_keypress(&self) -> KeyEvent {
-        loop {
-            if let Ok(Event::Key(keypress)) = event::read() {
-                break keypress;
+    /// Repeats what was typed after a Visual block `I`, `A` or `c` on the
+    /// other lines of the block
+    fn finish_block_insert(&mut self, block: BlockInsert) -> Result<()> {
+        let buffer = &mut self.buffers[self.buffer_index].1;
+        let (line, x) = block.start;
+        if buffer.line_count() != block.line_count {
+            return Ok(());
+        }
+        let added = buffer.line_length(line).saturating_sub(block.length);
+        if added == 0 {
+            return Ok(());
+        }
+        let text = buffer.grapheme_text(line, x, x + added);
+        for line in block.lines {
+            buffer.insert_at_column(line, block.column, &text, block.pad);
+        }
+        buffer.move_to(block.start, self.area.height)
+    }
+
+    /// Applies an operator to the Visual mode selection, ending Visual mode
+    fn visual_operator(&mut self, key: char) -> Result<()> {
+        let Some(mut selection) = self.selection() else {
+            return Ok(());
+        };
+        self.exit_visual();
+        if let 'X' | 'D' | 'Y' | 'C' | 'S' = key {
+            if selection.kind == SelectionKind::Block && key != 'Y' && key != 'S' {
+                selection.to_end = true;
+            } else {
+                selection.kind = SelectionKind::Lines;
+            }
+        }
+        let count = self.count.take();
+        let rows = self.area.height;
+        let range = selection.lines();
+        let buffer = &mut self.buffers[self.buffer_index].1;
+        let block_lines = (selection.kind == SelectionKind::Block).then(|| {
+            let left = selection.columns(buffer).0;
+            (range.start + 1..=range.end)
+                .filter(|line| buffer.line_width(*line) > left)
+                .collect::<Vec<_>>()
+        });
+        let start = match selection.kind {
+            SelectionKind::Chars => selection.bounds().0,
+            SelectionKind::Lines => (range.start, buffer.first_char_of(range.start)),
+            SelectionKind::Block => {
+                let (from, _, _) = buffer.selected_graphemes(&selection, range.start).unwrap();
+                (range.start, from)
+            }
+        };
+        match key {
+            'y' | 'Y' => {
+                let (first, last) = selection.bounds();
+                buffer.set_mark('[', first);
+                buffer.set_mark(']', last);
+                self.registers.yank(None, buffer.selection_text(&selection));
+                buffer.move_to(start, rows)?;
+            }
+            'd' | 'x' | 'X' | 'D' => {
+                self.registers.delete(None, buffer.selection_text(&selection));
+                buffer.delete_selection(&selection);
+                let position = buffer.cursor_position();
+                buffer.move_to(position, rows)?;
+            }
+            'c' | 's' | 'C' | 'S' => {
+                self.registers.delete(None, buffer.selection_text(&selection));
+                if selection.kind == SelectionKind::Lines {
+                    if range.end > range.start {
+                        buffer.delete_lines(Range {
+                            start: range.start + 1,
+                            end: range.end,
+                        });
+                    }
+                    buffer.delete_range((range.start, 0), (range.start, usize::MAX), MotionKind::Exclusive)?;
+                } else {
+                    buffer.delete_selection(&selection);
+                }
+                let position = buffer.cursor_position();
+                buffer.move_to_insert(position, rows)?;
+                self.mode = OperationMode::Insert;
+                if selection.kind == SelectionKind::Block {
+                    let lines = block_lines.unwrap_or_default();
+                    self.block_insert = Some(BlockInsert::new(buffer, lines, Some(selection.columns(buffer).0), false));
+                }
+            }
+            '>' | '<' => {
+                let amount = count.unwrap_or(1) as isize * if key == '>' { 1 } else { -1 };
+                buffer.shift_lines(range, amount, self.options.shiftwidth, self.options.expandtab);
+                self.cursor_to_line(range.start)?;
+            }
+            'J' => {
+                let column = buffer.join_lines(range, true);
+                buffer.move_to((range.start, column), rows)?;
+            }
+            '~' | 'u' | 'U' => {
+                buffer.map_selection(&selection, |text| match key {
+                    'u' => text.to_lowercase(),
+                    'U' => text.to_uppercase(),
+                    _ => toggle_case(text),
+                });
+                buffer.move_to(start, rows)?;
+            }
+            _ => {
+                let initial = if key == '!' { "'<,'>!" } else { "'<,'>" };
+                if let Some(command) = self.last_line.get_command_with(':', initial, &mut self.typeahead, |_| ()) {
+                    self.process_command(command);
+                }
             }
         }
+        Ok(())
     }
 
-    pub fn process_command(&mut self, command: String) {
-        match command {
-            _ => (),
+    /// Prompts for a pattern and searches for it, returning where the cursor
+    /// should go and how an operator would treat the motion
+    fn search_prompt(&mut self, direction: Direction) -> Result<Option<((usize, usize), MotionKind)>> {
+        let area = self.area;
+        let gutter = self.gutter();
+        let (width, sidescroll, wrap) = (self.text_width(), self.sidescroll(), self.wrap());
+        let incsearch = self.options.incsearch;
+        let hlsearch = self.options.hlsearch;
+        let buffer = &mut self.buffers[self.buffer_index].1;
+        let view = buffer.view();
+        let input = self.last_line.get_command_with(direction.prompt(), "", &mut self.typeahead, |typed| {
+            if !incsearch {
+                return;
+            }
+            buffer.restore_view(view);
+            let search = Search::parse(typed, direction);
+            let regex = search.regex().ok().filter(|_| !search.pattern.is_empty());
+            let current = regex
+                .as_ref()
+                .and_then(|regex| buffer.find(regex, view.0, direction))
+                .map(|(found, _)| found);
+            if let Some(found) = current {
+                buffer.move_to(found.start, area.height).ok();
+            }
+            buffer.scroll_to_cursor((area.height, width), wrap.as_ref());
+            if wrap.is_none() {
+                buffer.scroll_sideways(width, sidescroll);
+            }
+            let highlights = Highlights {
+                search: regex.as_ref().filter(|_| hlsearch),
+                current,
+                visual: None,
+            };
+            buffer.draw_rows(area, (&gutter, wrap.as_ref()), &highlights).ok();
+        });
+        buffer.restore_view(view);
+        let Some(input) = input else {
+            return Ok(None);
+        };
+        let mut search = Search::parse(&input, direction);
+        if search.pattern.is_empty() {
+            match &self.last_search {
+                Some(last) => search.pattern = last.pattern.clone(),
+                None => {
+                    self.error = Some("E35: No previous regular expression".to_string());
+                    return Ok(None);
+                }
+            }
+        }
+        self.last_search = Some(search);
+        self.search_next(direction)
+    }
+
+    /// Repeats the last search, `direction` being relative to the buffer
+    fn search_next(&mut self, direction: Direction) -> Result<Option<((usize, usize), MotionKind)>> {
+        let Some(search) = self.last_search.clone() else {
+            self.error = Some("E35: No previous regular expression".to_string());
+            return Ok(None);
+        };
+        let regex = match search.regex() {
+            Ok(regex) => regex,
+            Err(error) => {
+                self.error = Some(error.to_string());
+                return Ok(None);
+            }
+        };
+        self.no_highlight = false;
+        let buffer = &self.buffers[self.buffer_index].1;
+        let cursor = buffer.cursor_position();
+        let mut from = cursor;
+        let mut target = None;
+        for _ in 0..self.count.take().unwrap_or(1) {
+            let Some((found, mut wrapped)) = buffer.find(&regex, from, direction) else {
+                self.error = Some(format!("E486: Pattern not found: {}", search.pattern));
+                return Ok(None);
+            };
+            let mut result = search.target(buffer, &found);
+            if result.0 == from {
+                // an offset put us back where we started, so skip this match
+                let (next, wrapped_again) = buffer
+                    .find(&regex, found.start, direction)
+                    .unwrap_or((found, false));
+                wrapped |= wrapped_again;
+                result = search.target(buffer, &next);
+                from = next.start;
+            } else {
+                from = found.start;
+            }
+            self.message = wrapped.then(|| match direction {
+                Direction::Forward => "search hit BOTTOM, continuing at TOP".to_string(),
+                Direction::Backward => "search hit TOP, continuing at BOTTOM".to_string(),
+            });
+            target = Some(result);
+        }
+        Ok(target)
+    }
+
+    /// Searches for the whole word under the cursor, like `*` and `#`
+    fn search_word(&mut self, direction: Direction) -> Result<Option<((usize, usize), MotionKind)>> {
+        let buffer = &self.buffers[self.buffer_index].1;
+        let Some(word) = buffer.word_under_cursor() else {
+            self.error = Some("E348: No string under cursor".to_string());
+            return Ok(None);
+        };
+        let mut pattern = regex::escape(&word, direction.prompt());
+        if word.starts_with(crate::buffer::is_keyword) {
+            pattern = format!("\\<{pattern}\\>");
+        }
+        self.last_search = Some(Search {
+            pattern,
+            direction,
+            offset: Default::default(),
+        });
+        self.search_next(direction)
+    }
+
+    /// Resolves a motion key into a target position, for both plain cursor
+    /// movement and operators
+    fn motion(&mut self, key: char) -> Result<Option<((usize, usize), MotionKind)>> {
+        let direction = self.last_search.as_ref().map_or(Direction::Forward, |search| search.direction);
+        match key {
+            '/' => self.search_prompt(Direction::Forward),
+            '?' => self.search_prompt(Direction::Backward),
+            'n' => self.search_next(direction),
+            'N' => self.search_next(direction.reverse()),
+            '*' => self.search_word(Direction::Forward),
+            '#' => self.search_word(Direction::Backward),
+            'j' | 'k' | 'G' => {
+                let line = self.current_line();
+                let count = self.count.take();
+                let target = match key {
+                    'j' => (line + count.unwrap_or(1)).min(self.last_line()),
+                    'k' => line.saturating_sub(count.unwrap_or(1)),
+                    _ => count.map_or(self.last_line(), |count| count.saturating_sub(1).min(self.last_line())),
+                };
+                let column = self.buffers[self.buffer_index].1.first_char_of(target);
+                Ok(Some(((target, column), MotionKind::Linewise)))
+            }
+            '%' => {
+                let buffer = &self.buffers[self.buffer_index].1;
+                if let Some(count) = self.count.take() {
+                    let line = (count * buffer.line_count()).div_ceil(100).clamp(1, buffer.line_count()) - 1;
+                    return Ok(Some(((line, buffer.first_char_of(line)), MotionKind::Linewise)));
+                }
+                let found = buffer.matching_bracket(buffer.cursor_position());
+                Ok(found.map(|position| (position, MotionKind::Inclusive)))
+            }
+            '\'' | '`' => {
+                let event::KeyCode::Char(name) = self.capture_keypress().code else {
+                    return Ok(None);
+                };
+                let position = match self.find_mark(name) {
+                    Ok(position) => position,
+                    Err(error) => {
+                        self.error = Some(error.to_string());
+                        return Ok(None);
+                    }
+                };
+                if key == '`' {
+                    return Ok(Some((position, MotionKind::Exclusive)));
+                }
+                let column = self.buffers[self.buffer_index].1.first_char_of(position.0);
+                Ok(Some(((position.0, column), MotionKind::Linewise)))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Reads the motion following an operator key, the operator typed again
+    /// meaning `count` lines from the cursor line
+    fn operator_motion(&mut self, operator: char) -> Result<Option<((usize, usize), MotionKind)>> {
+        let count = self.count.take();
+        let keypress = self.capture_keypress();
+        let event::KeyCode::Char(key) = keypress.code else {
+            return Ok(None);
+        };
+        if key == operator {
+            let last = self.current_line() + count.unwrap_or(1) - 1;
+            return Ok(Some(((last, 0), MotionKind::Linewise)));
+        }
+        self.count = count;
+        let buffer_index = self.buffer_index;
+        let motion = self.motion(key)?;
+        if self.buffer_index != buffer_index {
+            // a mark in another file: the cursor went there instead
+            return Ok(None);
+        }
+        Ok(motion)
+    }
+
+    /// `!{motion}`: starts a `:{range}!` command line for the lines moved
+    /// over
+    fn filter_operator(&mut self) -> Result<()> {
+        let Some((target, _)) = self.operator_motion('!')? else {
+            return Ok(());
+        };
+        let cursor = self.current_line();
+        let target = target.0.min(self.last_line());
+        let relative = |line: usize| match line.cmp(&cursor) {
+            std::cmp::Ordering::Equal => ".".to_string(),
+            std::cmp::Ordering::Greater => format!(".+{}", line - cursor),
+            std::cmp::Ordering::Less => format!(".-{}", cursor - line),
+        };
+        let range = if target == cursor {
+            ".".to_string()
+        } else {
+            format!("{},{}", relative(cursor.min(target)), relative(cursor.max(target)))
+        };
+        let initial = format!("{range}!");
+        if let Some(command) = self.last_line.get_command_with(':', &initial, &mut self.typeahead, |_| ()) {
+            self.process_command(command);
+        }
+        Ok(())
+    }
+
+    /// `{count}r{char}`: replaces as many graphemes from the cursor with
+    /// `char`, or with a single line break for Enter
+    fn replace_chars(&mut self) -> Result<()> {
+        let count = self.count.take().unwrap_or(1);
+        let keypress = self.capture_keypress();
+        let buffer = &mut self.buffers[self.buffer_index].1;
+        let (line, x) = buffer.cursor_position();
+        if x + count > buffer.line_length(line) {
+            return Ok(());
+        }
+        let character = match keypress.code {
+            event::KeyCode::Char(c) => c,
+            event::KeyCode::Tab => '\t',
+            event::KeyCode::Enter => {
+                buffer.overwrite((line, x), count, "");
+                buffer.split_line((line, x));
+                return buffer.move_to((line + 1, 0), self.area.height);
+            }
+            _ => return Ok(()),
+        };
+        buffer.overwrite((line, x), count, &character.to_string().repeat(count));
+        buffer.move_to((line, x + count - 1), self.area.height)
+    }
+
+    /// `{count}J` and `{count}gJ`: joins `count` lines, at least two, with
+    /// or without `spaces` in between
+    fn join(&mut self, spaces: bool) -> Result<()> {
+        let count = self.count.take().unwrap_or(2).max(2);
+        let line = self.current_line();
+        if line == self.last_line() {
+            return Ok(());
         }
+        let range = with_count(Range::line(line), Some(count), self.last_line());
+        let buffer = &mut self.buffers[self.buffer_index].1;
+        let column = buffer.join_lines(range, spaces);
+        buffer.move_to((line, column), self.area.height)
     }
+
+    /// `{count}~`: swaps the case of `count` graphemes from the cursor,
+    /// moving past them
+    fn toggle_case(&mut self) -> Result<()> {
+        let count = self.count.take().unwrap_or(1);
+        let buffer = &mut self.buffers[self.buffer_index].1;
+        let (line, x) = buffer.cursor_position();
+        let to = (x + count).min(buffer.line_length(line));
+        if x >= to {
+            return Ok(());
+        }
+        let text = buffer.grapheme_text(line, x, to);
+        buffer.overwrite((line, x), to - x, &toggle_case(&text));
+        buffer.move_to((line, to), self.area.height)
+    }
+
+    /// Visual `Ctrl-A` and `Ctrl-X`: adds `sign` times the count to the
+    /// first number of every selected line, the amount growing with each
+    /// number when `progressive`, like `g Ctrl-A` and `g Ctrl-X`
+    fn visual_increment(&mut self, sign: i64, progressive: bool) -> Result<()> {
+        let Some(selection) = self.selection() else {
+            return Ok(());
+        };
+        self.exit_visual();
+        let delta = sign * self.count.take().unwrap_or(1) as i64;
+        let buffer = &mut self.buffers[self.buffer_index].1;
+        let range = selection.lines();
+        let mut amount = delta;
+        for line in range.start..=range.end {
+            let (from, to, _) = buffer.selected_graphemes(&selection, line).unwrap();
+            if buffer.increment(line, from, to, amount).is_some() && progressive {
+                amount += delta;
+            }
+        }
+        let start = match selection.kind {
+            SelectionKind::Lines => (range.start, 0),
+            _ => (range.start, buffer.selected_graphemes(&selection, range.start).unwrap().0),
+        };
+        buffer.move_to(start, self.area.height)
+    }
+
+    /// Waits for the motion of an operator such as `d` or `c` and applies it
+    fn operator(&mut self, operator: char, rows: usize) -> Result<()> {
+        let Some((target, kind)) = self.operator_motion(operator)? else {
+            return Ok(());
+        };
+        let cursor = self.buffers[self.buffer_index].1.cursor_position();
+        let current_buffer = &mut self.buffers[self.buffer_index].1;
+        let target = (target.0.min(current_buffer.line_count() - 1), target.1);
+        self.registers.delete(None, current_buffer.text_range(cursor, target, kind));
+        if operator == 'c' && kind == MotionKind::Linewise {
+            let (first, last) = (cursor.0.min(target.0), cursor.0.max(target.0));
+            if last > first {
+                current_buffer.delete_range((first + 1, 0), (last, 0), kind)?;
+            }
+            current_buffer.delete_range((first, 0), (first, usize::MAX), MotionKind::Exclusive)?;
+        } else {
+            current_buffer.delete_range(cursor, target, kind)?;
+        }
+        let position = current_buffer.cursor_position();
+        current_buffer.move_to(position, rows)?;
+        if operator == 'c' {
+            self.mode = OperationMode::Insert;
+        }
+        Ok(())
+    }
+
     pub fn process_keypress(&mut self, keypress: KeyEvent) -> Result<()> {
+        let rows: u16 = self.area.height.try_into()?;
+        self.clear_message();
+        if let OperationMode::Visual(kind) = self.mode {
+            if self.process_visual_keypress(keypress, kind)? {
+                self.count = None;
+                return Ok(());
+            }
+        }
+        if let (OperationMode::Command | OperationMode::Visual(_), event::KeyCode::Char(digit @ '0'..='9')) =
+            (&self.mode, keypress.code)
+        {
+            // with Control, `6` is `Ctrl-^` as terminals send it
+            let control = keypress.modifiers.contains(KeyModifiers::CONTROL);
+            if (digit != '0' || self.count.is_some()) && !control {
+                let count = self.count.unwrap_or(0) * 10 + digit.to_digit(10).unwrap() as usize;
+                self.count = Some(count);
+                return Ok(());
+            }
+        }
+        match keypress.code {
+            event::KeyCode::Char('o' | 'i') | event::KeyCode::Tab
+                if matches!(self.mode, OperationMode::Command)
+                    && (keypress.code == event::KeyCode::Tab || keypress.modifiers.contains(KeyModifiers::CONTROL)) =>
+            {
+                let count = self.count.take().unwrap_or(1) as isize;
+                self.older_jump(if keypress.code == event::KeyCode::Char('o') { count } else { -count })?;
+            }
+            event::KeyCode::Char(key @ ('/' | '?' | 'n' | 'N' | '*' | '#' | 'G' | '%' | '\'' | '`'))
+                if matches!(self.mode, OperationMode::Command | OperationMode::Visual(_)) =>
+            {
+                let from = self.here();
+                if let Some((target, _)) = self.motion(key)? {
+                    self.jump(from, target)?;
+                }
+            }
+            event::KeyCode::Char('m') if matches!(self.mode, OperationMode::Command) => {
+                if let event::KeyCode::Char(name) = self.capture_keypress().code {
+                    let position = self.buffers[self.buffer_index].1.cursor_position();
+                    if let Err(error) = self.set_mark(name, position) {
+                        self.error = Some(error.to_string());
+                    }
+                }
+            }
+            event::KeyCode::Char(operator @ ('d' | 'c'))
+                if matches!(self.mode, OperationMode::Command) =>
+            {
+                self.operator(operator, rows.into())?;
+            }
+            event::KeyCode::Char('!') if matches!(self.mode, OperationMode::Command) => {
+                self.filter_operator()?;
+            }
+            event::KeyCode::Char('&') if matches!(self.mode, OperationMode::Command) => {
+                self.process_command("s".to_string());
+            }
+            event::KeyCode::Char('r') if matches!(self.mode, OperationMode::Command) => {
+                self.replace_chars()?;
+            }
+            event::KeyCode::Char('J') if matches!(self.mode, OperationMode::Command) => {
+                self.join(true)?;
+            }
+            event::KeyCode::Char('w')
+                if matches!(self.mode, OperationMode::Command) && keypress.modifiers.contains(KeyModifiers::CONTROL) =>
+            {
+                let key = self.typeahead.read();
+                if let Err(error) = self.window_command(key) {
+                    self.error = Some(error.to_string());
+                }
+            }
+            event::KeyCode::Char('^' | '6')
+                if matches!(self.mode, OperationMode::Command) && keypress.modifiers.contains(KeyModifiers::CONTROL) =>
+            {
+                let index = match self.count.take() {
+                    Some(number) => self.find_buffer(&number.to_string()),
+                    None => self.alternate.ok_or_else(|| "E23: No alternate file".into()),
+                };
+                match index {
+                    Ok(index) => self.edit_buffer(index)?,
+                    Err(error) => self.error = Some(error.to_string()),
+                }
+            }
+            event::KeyCode::Char('~') if matches!(self.mode, OperationMode::Command) => {
+                self.toggle_case()?;
+            }
+            event::KeyCode::Char(key @ ('a' | 'x'))
+                if matches!(self.mode, OperationMode::Command) && keypress.modifiers.contains(KeyModifiers::CONTROL) =>
+            {
+                let delta = self.count.take().unwrap_or(1) as i64;
+                let buffer = &mut self.buffers[self.buffer_index].1;
+                let (line, x) = buffer.cursor_position();
+                if let Some(end) = buffer.increment(line, x, usize::MAX, if key == 'a' { delta } else { -delta }) {
+                    buffer.move_to((line, end), rows.into())?;
+                }
+            }
+            event::KeyCode::Char('g') if matches!(self.mode, OperationMode::Command | OperationMode::Visual(_)) => {
+                self.g_command()?;
+            }
+            event::KeyCode::Char('z') if matches!(self.mode, OperationMode::Command | OperationMode::Visual(_)) => {
+                self.z_command();
+            }
+            _ => self.process_simple_keypress(keypress, rows)?,
+        }
+        self.count = None;
+        Ok(())
+    }
+
+    fn process_simple_keypress(&mut self, keypress: KeyEvent, rows: u16) -> Result<()> {
         let current_buffer = &mut self.buffers[self.buffer_index].1;
-        let (_, mut rows) = size()?;
-        rows -= 1;
-        match &self.mode {
+        match self.mode {
             OperationMode::Command => match keypress.code {
                 event::KeyCode::Char('q') => {
                     self.wants_out = true;
                 }
-                event::KeyCode::Char('j') => {
-                    current_buffer.move_down(1, rows.into())?;
-                }
-                event::KeyCode::Char('k') => {
-                    current_buffer.move_up(1)?;
+                event::KeyCode::Char('v') if keypress.modifiers.contains(KeyModifiers::CONTROL) => {
+                    self.start_visual(SelectionKind::Block);
                 }
-                event::KeyCode::Char('l') => {
-                    current_buffer.move_right(1)?;
+                event::KeyCode::Char('v') => {
+                    self.start_visual(SelectionKind::Chars);
                 }
-                event::KeyCode::Char('h') => {
-                    current_buffer.move_left(1)?;
+                event::KeyCode::Char('V') => {
+                    self.start_visual(SelectionKind::Lines);
                 }
                 event::KeyCode::Char('i' | 'I') => {
                     if keypress.modifiers == KeyModifiers::SHIFT {
@@ -100,66 +2438,399 @@ impl Editor {
                     }
                     self.mode = OperationMode::Insert;
                 }
+                event::KeyCode::Char('R') => {
+                    self.replaced.clear();
+                    self.mode = OperationMode::Replace;
+                }
                 event::KeyCode::Char('x') => {
                     current_buffer.remove_char()?;
                 }
                 event::KeyCode::Char('o') => {
-                    current_buffer.new_line_after_cursor()?;
+                    current_buffer.new_line_after_cursor(rows.into())?;
                     self.mode = OperationMode::Insert;
                 }
                 event::KeyCode::Char('O') => {
                     current_buffer.new_line_before_cursor()?;
                     self.mode = OperationMode::Insert;
                 }
-                event::KeyCode::Char('_') => {
-                    current_buffer.move_to_first_char()?;
-                }
-                event::KeyCode::Char('0') => {
-                    current_buffer.move_start_of_line()?;
-                }
-                event::KeyCode::Char('$') => {
-                    current_buffer.move_end_of_line()?;
-                }
                 event::KeyCode::Char(':') => {
-                    self.last_line.get_command();
-                }
-                _ => (),
-            },
-            OperationMode::Insert => match keypress.code {
-                event::KeyCode::Esc => {
-                    self.mode = OperationMode::default();
-                    current_buffer.move_left(1)?;
+                    if let Some(command) = self.last_line.get_command(':', &mut self.typeahead) {
+                        self.process_command(command);
+                    }
                 }
                 event::KeyCode::Char(key) => {
-                    current_buffer.insert_char(key)?;
-                }
-                event::KeyCode::Tab => {
-                    current_buffer.insert_char('\t')?;
-                }
-                event::KeyCode::Backspace => {
-                    current_buffer.remove_char_before_cursor()?;
+                    simple_motion(current_buffer, key, rows)?;
                 }
                 _ => (),
             },
-            OperationMode::Escape => {
-                self.last_line.get_command();
-                // ();
+            OperationMode::Visual(_) => {
+                if let event::KeyCode::Char(key) = keypress.code {
+                    if simple_motion(current_buffer, key, rows)? && !"jk".contains(key) {
+                        self.visual_to_end = key == '$';
+                    }
+                }
+            }
+            OperationMode::Replace => {
+                let (line, x) = current_buffer.cursor_position();
+                let typed = match keypress.code {
+                    event::KeyCode::Esc => {
+                        self.mode = OperationMode::default();
+                        current_buffer.set_mark('^', (line, x));
+                        return current_buffer.move_left(1);
+                    }
+                    event::KeyCode::Backspace => {
+                        if x == 0 {
+                            return Ok(());
+                        }
+                        if let Some(original) = self.replaced.pop() {
+                            current_buffer.overwrite((line, x - 1), 1, &original);
+                        }
+                        return current_buffer.move_to_insert((line, x - 1), rows.into());
+                    }
+                    event::KeyCode::Enter => {
+                        self.replaced.clear();
+                        current_buffer.split_line((line, x));
+                        return current_buffer.move_to_insert((line + 1, 0), rows.into());
+                    }
+                    event::KeyCode::Char(c) => c,
+                    event::KeyCode::Tab => '\t',
+                    _ => return Ok(()),
+                };
+                let count = usize::from(x < current_buffer.line_length(line));
+                let original = current_buffer.overwrite((line, x), count, &typed.to_string());
+                self.replaced.push(original);
+                current_buffer.move_to_insert((line, x + 1), rows.into())?;
+            }
+            OperationMode::Insert => self.process_insert_keypress(keypress)?,
+        }
+        Ok(())
+    }
+
+    fn process_insert_keypress(&mut self, keypress: KeyEvent) -> Result<()> {
+        let rows = self.area.height;
+        let control = keypress.modifiers.contains(KeyModifiers::CONTROL);
+        let (shiftwidth, expandtab) = (self.options.shiftwidth, self.options.expandtab);
+        let buffer = &mut self.buffers[self.buffer_index].1;
+        let (line, x) = buffer.cursor_position();
+        let target = match keypress.code {
+            event::KeyCode::Esc => {
                 self.mode = OperationMode::default();
+                buffer.set_mark('^', (line, x));
+                buffer.move_left(1)?;
+                if let Some(block) = self.block_insert.take() {
+                    self.finish_block_insert(block)?;
+                }
+                return Ok(());
+            }
+            event::KeyCode::Char('o') if control => return self.insert_command(),
+            event::KeyCode::Char('r') if control => {
+                let event::KeyCode::Char(name) = self.capture_keypress().code else {
+                    return Ok(());
+                };
+                let Some(register) = self.registers.get(name) else {
+                    return Ok(());
+                };
+                let mut text = register.lines.join("\n");
+                if register.kind == RegisterKind::Linewise {
+                    text.push('\n');
+                }
+                self.buffers[self.buffer_index].1.insert_text((line, x), &text)
+            }
+            event::KeyCode::Char('v') if control => {
+                let Some(c) = self.literal_char() else {
+                    return Ok(());
+                };
+                self.buffers[self.buffer_index].1.insert_text((line, x), &c.to_string())
+            }
+            event::KeyCode::Char('w' | 'u') if control && x > 0 => {
+                let start = match keypress.code {
+                    event::KeyCode::Char('w') => buffer.word_start_before((line, x)),
+                    _ => Some(buffer.first_char_of(line)).filter(|first| *first < x).unwrap_or(0),
+                };
+                buffer.delete_range((line, start), (line, x), MotionKind::Exclusive)?;
+                (line, start)
+            }
+            event::KeyCode::Char('t' | 'd') if control => {
+                let length = buffer.line_length(line);
+                let amount = if keypress.code == event::KeyCode::Char('t') { 1 } else { -1 };
+                buffer.shift_lines(Range::line(line), amount, shiftwidth, expandtab);
+                (line, (x + buffer.line_length(line)).saturating_sub(length))
+            }
+            event::KeyCode::Char('h' | 'w' | 'u') if control => {
+                buffer.remove_char_before_cursor()?;
+                buffer.cursor_position()
+            }
+            event::KeyCode::Backspace => {
+                buffer.remove_char_before_cursor()?;
+                buffer.cursor_position()
+            }
+            event::KeyCode::Char(_) if control => return Ok(()),
+            event::KeyCode::Char(c) => buffer.insert_text((line, x), &c.to_string()),
+            event::KeyCode::Tab => buffer.insert_text((line, x), "\t"),
+            event::KeyCode::Enter => {
+                buffer.split_line((line, x));
+                (line + 1, 0)
+            }
+            event::KeyCode::Delete if x < buffer.line_length(line) => {
+                buffer.overwrite((line, x), 1, "");
+                (line, x)
+            }
+            event::KeyCode::Delete if line < buffer.line_count() - 1 => {
+                buffer.join_lines(Range::line(line), false);
+                (line, x)
+            }
+            event::KeyCode::Left => (line, x.saturating_sub(1)),
+            event::KeyCode::Right => (line, x + 1),
+            event::KeyCode::Up => (line.saturating_sub(1), x),
+            event::KeyCode::Down => (line + 1, x),
+            event::KeyCode::Home => (line, 0),
+            event::KeyCode::End => (line, usize::MAX),
+            _ => return Ok(()),
+        };
+        self.buffers[self.buffer_index].1.move_to_insert(target, rows)
+    }
+
+    /// `Ctrl-O` in Insert mode: runs one Command mode command, then goes
+    /// back to Insert mode unless the command left it
+    fn insert_command(&mut self) -> Result<()> {
+        self.mode = OperationMode::Command;
+        loop {
+            self.draw()?;
+            let keypress = self.capture_keypress();
+            self.process_keypress(keypress)?;
+            if self.wants_out || (self.count.is_none() && !matches!(self.mode, OperationMode::Visual(_))) {
+                break;
             }
         }
-        let mut stdout = stdout();
-        stdout.execute(Clear(ClearType::All))?;
-        current_buffer.draw_rows(rows.into())?;
+        if self.mode == OperationMode::Command {
+            self.mode = OperationMode::Insert;
+        }
         Ok(())
     }
+
+    /// `Ctrl-V` in Insert mode: the next key as a character, or the
+    /// character given by its code as `u20ac`, `U0001f600`, `x41`, `o101`
+    /// or `065`
+    fn literal_char(&mut self) -> Option<char> {
+        let keypress = self.capture_keypress();
+        let letter = match keypress.code {
+            event::KeyCode::Char(c) if keypress.modifiers.contains(KeyModifiers::CONTROL) => {
+                return Some(char::from(c.to_ascii_lowercase() as u8 & 0x1f));
+            }
+            event::KeyCode::Char(c) => c,
+            event::KeyCode::Tab => return Some('\t'),
+            event::KeyCode::Enter => return Some('\r'),
+            event::KeyCode::Esc => return Some('\x1b'),
+            event::KeyCode::Backspace => return Some('\x7f'),
+            _ => return None,
+        };
+        let (radix, length, mut value, mut digits) = match letter {
+            'u' => (16, 4, 0, 0),
+            'U' => (16, 8, 0, 0),
+            'x' | 'X' => (16, 2, 0, 0),
+            'o' | 'O' => (8, 3, 0, 0),
+            '0'..='9' => (10, 3, letter.to_digit(10).unwrap(), 1),
+            _ => return Some(letter),
+        };
+        while digits < length {
+            let keypress = self.capture_keypress();
+            match keypress.code {
+                event::KeyCode::Char(c) if c.is_digit(radix) && !keypress.modifiers.contains(KeyModifiers::CONTROL) => {
+                    value = value * radix + c.to_digit(radix).unwrap();
+                    digits += 1;
+                }
+                _ => {
+                    self.typeahead.unread(keypress);
+                    break;
+                }
+            }
+        }
+        if digits == 0 {
+            return Some(letter);
+        }
+        char::from_u32(value)
+    }
+}
+
+impl Context for Editor {
+    fn current_line(&self) -> usize {
+        self.buffers[self.buffer_index].1.cursor_position().0
+    }
+
+    fn last_line(&self) -> usize {
+        self.buffers[self.buffer_index].1.line_count() - 1
+    }
+
+    fn mark_line(&self, mark: char) -> Option<usize> {
+        Some(self.buffers[self.buffer_index].1.mark(mark)?.0)
+    }
+
+    fn search_line(&self, pattern: &str, line: usize, direction: Direction) -> std::result::Result<usize, String> {
+        let pattern = match (pattern, &self.last_search) {
+            ("", Some(last)) => &last.pattern,
+            ("", None) => return Err("E35: No previous regular expression".to_string()),
+            (pattern, _) => pattern,
+        };
+        let regex = regex::Regex::new(pattern).map_err(|error| error.to_string())?;
+        let buffer = &self.buffers[self.buffer_index].1;
+        let total = buffer.line_count();
+        (1..=total)
+            .map(|step| match direction {
+                Direction::Forward => (line + step) % total,
+                Direction::Backward => (line + total - step) % total,
+            })
+            .find(|line| buffer.matches_line(&regex, *line))
+            .ok_or_else(|| format!("E486: Pattern not found: {pattern}"))
+    }
+
+    fn last_substitute_pattern(&self) -> Option<String> {
+        self.last_substitute.as_ref().map(|substitute| substitute.pattern.clone())
+    }
+}
+
+/// Moves the cursor for the keys that do the same in Command and Visual
+/// mode, returning whether `key` was one of them
+fn simple_motion(buffer: &mut Buffer, key: char, rows: u16) -> Result<bool> {
+    match key {
+        'j' => buffer.move_down(1, rows.into())?,
+        'k' => buffer.move_up(1)?,
+        'l' => buffer.move_right(1)?,
+        'h' => buffer.move_left(1)?,
+        '_' => buffer.move_to_first_char()?,
+        '0' => buffer.move_start_of_line()?,
+        '$' => buffer.move_end_of_line()?,
+        _ => return Ok(false),
+    }
+    Ok(true)
+}
+
+/// The size of the terminal, as (columns, rows)
+fn screen_size() -> Result<(usize, usize)> {
+    let (columns, rows) = size()?;
+    Ok((columns.into(), rows.into()))
+}
+
+/// The part of a window's `area` for the text, above its status line when
+/// windows have one
+fn text_area(area: Area, status: bool) -> Area {
+    Area {
+        height: area.height.saturating_sub(usize::from(status)).max(1),
+        ..area
+    }
+}
+
+/// Reads `path` into a new buffer, an empty one for a new file, with the
+/// message telling what was read
+fn load(path: &str) -> (Buffer, String) {
+    match read(path) {
+        Ok(file) => {
+            let lines = String::from_utf8_lossy(&file).lines().count();
+            let mut buffer = Buffer::new(&file);
+            let readonly = metadata(path).is_ok_and(|metadata| metadata.permissions().readonly());
+            buffer.set_readonly(readonly);
+            let flags = match (readonly, buffer.file_format()) {
+                (true, "dos") => "[readonly][dos] ",
+                (true, _) => "[readonly] ",
+                (false, "dos") => "[dos] ",
+                (false, _) => "",
+            };
+            (buffer, format!("\"{path}\" {flags}{lines}L, {}B", file.len()))
+        }
+        Err(_) => (Buffer::new(&[]), format!("\"{path}\" [New]")),
+    }
+}
+
+/// How a buffer's file is shown, `[No Name]` when it has none
+fn display_name(path: &str) -> &str {
+    if path.is_empty() {
+        "[No Name]"
+    } else {
+        path
+    }
+}
+
+/// Swaps the case of every letter in `text`, as `~` does
+fn toggle_case(text: &str) -> String {
+    text.chars()
+        .flat_map(|c| -> Box<dyn Iterator<Item = char>> {
+            if c.is_lowercase() {
+                Box::new(c.to_uppercase())
+            } else {
+                Box::new(c.to_lowercase())
+            }
+        })
+        .collect()
+}
+
+/// Shows a match of `:s///c` and asks what to do with it
+fn confirm_substitution(
+    buffer: &Buffer,
+    found: Match,
+    area: Area,
+    display: (&Gutter, Option<&Wrap>),
+    replacement: &str,
+    typeahead: &mut Typeahead,
+) -> Result<Confirm> {
+    let mut stdout = stdout();
+    let highlights = Highlights {
+        current: Some(found),
+        ..Default::default()
+    };
+    buffer.draw_rows(area, display, &highlights)?;
+    let last_row: u16 = (screen_size()?.1 - 1).try_into()?;
+    let prompt = format!("replace with {replacement} (y/n/a/q/l)?");
+    queue!(stdout, cursor::SavePosition, MoveTo(0, last_row), Clear(ClearType::CurrentLine))?;
+    queue!(stdout, Print(prompt), cursor::RestorePosition)?;
+    stdout.flush()?;
+    loop {
+        match typeahead.read().code {
+            event::KeyCode::Char('y') => return Ok(Confirm::Yes),
+            event::KeyCode::Char('n') => return Ok(Confirm::No),
+            event::KeyCode::Char('a') => return Ok(Confirm::All),
+            event::KeyCode::Char('q') | event::KeyCode::Esc => return Ok(Confirm::Quit),
+            event::KeyCode::Char('l') => return Ok(Confirm::Last),
+            _ => (),
+        }
+    }
+}
+
+/// Parses the `[x] [count]` argument of `:d`, `:y`, `:pu` and the like
+fn register_and_count(argument: &str) -> std::result::Result<(Option<char>, Option<usize>), String> {
+    let mut rest = argument.trim();
+    let register = rest
+        .chars()
+        .next()
+        .filter(|c| !c.is_ascii_digit() && Registers::is_valid(*c));
+    if let Some(register) = register {
+        rest = rest[register.len_utf8()..].trim_start();
+    }
+    if rest.is_empty() {
+        return Ok((register, None));
+    }
+    match rest.parse::<usize>() {
+        Ok(count) if count > 0 => Ok((register, Some(count))),
+        Ok(_) => Err("E939: Positive count required".to_string()),
+        Err(_) => Err(format!("E488: Trailing characters: {rest}")),
+    }
+}
+
+/// The `count` lines starting at the end of `range`, as a count after a
+/// command means
+fn with_count(range: Range, count: Option<usize>, last_line: usize) -> Range {
+    match count {
+        Some(count) => Range {
+            start: range.end,
+            end: (range.end + count - 1).min(last_line),
+        },
+        None => range,
+    }
 }
 
 impl From<Vec<String>> for Editor {
     fn from(buffers: Vec<String>) -> Self {
         let mut vector = Vec::new();
         for path in buffers {
-            let file = read(&path).unwrap_or_default();
-            let buffer = Buffer::new(&file);
+            let (buffer, _) = load(&path);
             vector.push((path, buffer));
         }
         if vector.is_empty() {
@@ -169,6 +2840,7 @@ impl From<Vec<String>> for Editor {
         }
         Editor {
             mode: OperationMode::default(),
+            args: ArgList::new(vector.iter().map(|(path, _)| path.clone()).filter(|path| !path.is_empty()).collect()),
             buffers: vector,
             ..Editor::new()
         }
@@ -180,16 +2852,34 @@ struct CommandLine {
 }
 
 impl CommandLine {
-    pub fn get_command(&mut self) -> String {
+    /// Reads a line typed after `prompt` on the last row; `None` if cancelled
+    pub fn get_command(&mut self, prompt: char, typeahead: &mut Typeahead) -> Option<String> {
+        self.get_command_with(prompt, "", typeahead, |_| ())
+    }
+
+    /// Like `get_command`, calling `on_change` with the text every time it
+    /// is edited, before the prompt is redrawn
+    pub fn get_command_with(
+        &mut self,
+        prompt: char,
+        initial: &str,
+        typeahead: &mut Typeahead,
+        mut on_change: impl FnMut(&str),
+    ) -> Option<String> {
         let mut stdout = stdout();
         queue!(stdout, cursor::SavePosition).unwrap();
         queue!(stdout, MoveTo(0, 10000)).unwrap();
-        queue!(stdout, Print(':')).unwrap();
+        queue!(stdout, terminal::Clear(ClearType::CurrentLine)).unwrap();
+        queue!(stdout, Print(prompt), Print(initial)).unwrap();
         stdout.flush().unwrap();
-        let mut command = String::new();
-        while let Ok(Event::Key(keypress)) = event::read() {
-            match keypress.code {
-                event::KeyCode::Enter | event::KeyCode::Esc => break,
+        let mut command = initial.to_string();
+        loop {
+            match typeahead.read().code {
+                event::KeyCode::Enter => break,
+                event::KeyCode::Esc => {
+                    execute!(stdout, cursor::RestorePosition).unwrap();
+                    return None;
+                }
                 event::KeyCode::Backspace => {
                     command.pop();
                 }
@@ -198,13 +2888,15 @@ impl CommandLine {
                 }
                 _ => (),
             }
+            on_change(&command);
+            queue!(stdout, MoveTo(0, 10000)).unwrap();
             queue!(stdout, terminal::Clear(ClearType::CurrentLine)).unwrap();
-            queue!(stdout, Print("\r:")).unwrap();
+            queue!(stdout, Print('\r'), Print(prompt)).unwrap();
             queue!(stdout, Print(&command)).unwrap();
             stdout.flush().unwrap();
         }
         execute!(stdout, cursor::RestorePosition).unwrap();
-        self.history.push(command);
-        self.history.last().unwrap().to_string()
+        self.history.push(command.clone());
+        Some(command)
     }
 }
@@ -0,0 +1,434 @@
+//! Parsing of ex command lines, the text typed after `:`
+
+use crate::buffer::search::Direction;
+
+/// An inclusive range of buffer lines, zero-based
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Range {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Range {
+    pub fn line(line: usize) -> Self {
+        Self {
+            start: line,
+            end: line,
+        }
+    }
+}
+
+/// Where a line specification starts from, before any `+N`/`-N`
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Address {
+    /// `.`
+    Current,
+    /// `$`
+    Last,
+    /// A line number as typed, `0` meaning before the first line
+    Number(usize),
+    /// `'x`
+    Mark(char),
+    /// `/pat/` or `?pat?`, an empty pattern meaning the last one
+    Pattern(String, Direction),
+    /// `\/` and `\?`: the last search pattern
+    LastSearch(Direction),
+    /// `\&`: the last substitute pattern
+    LastSubstitute,
+}
+
+/// One address of a range, e.g. `/foo//bar/+2`
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct LineSpec {
+    /// Each address is looked up from where the previous one landed
+    pub addresses: Vec<Address>,
+    pub offset: isize,
+}
+
+/// What separates two line specifications
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Separator {
+    /// `,`: every address is relative to the cursor line
+    Comma,
+    /// `;`: the next address is relative to this one
+    Semicolon,
+}
+
+/// What addresses are resolved against
+pub trait Context {
+    fn current_line(&self) -> usize;
+    fn last_line(&self) -> usize;
+    fn mark_line(&self, mark: char) -> Option<usize>;
+    /// The first line after (or before) `line` matching `pattern`, wrapping
+    /// around the buffer. An empty pattern is the last search pattern.
+    fn search_line(
+        &self,
+        pattern: &str,
+        line: usize,
+        direction: Direction,
+    ) -> Result<usize, String>;
+    fn last_substitute_pattern(&self) -> Option<String>;
+}
+
+/// A parsed command line
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct Command {
+    pub specs: Vec<(LineSpec, Separator)>,
+    /// The full name of the command, or the name as typed if unknown
+    pub name: String,
+    pub bang: bool,
+    pub argument: String,
+    /// What follows a `|`, another command to run after this one
+    pub next: Option<String>,
+}
+
+/// Known commands and the length of their shortest abbreviation
+const COMMANDS: &[(&str, usize)] = &[
+    ("!", 1),
+    ("&", 1),
+    ("<", 1),
+    (">", 1),
+    ("Next", 1),
+    ("argdo", 5),
+    ("args", 2),
+    ("bNext", 2),
+    ("bdelete", 2),
+    ("bnext", 2),
+    ("bprevious", 2),
+    ("bufdo", 5),
+    ("buffer", 1),
+    ("buffers", 7),
+    ("changes", 7),
+    ("close", 3),
+    ("copy", 2),
+    ("delete", 1),
+    ("edit", 1),
+    ("files", 5),
+    ("first", 3),
+    ("global", 1),
+    ("join", 1),
+    ("jumps", 2),
+    ("last", 2),
+    ("ls", 2),
+    ("mark", 2),
+    ("marks", 5),
+    ("messages", 3),
+    ("move", 1),
+    ("next", 1),
+    ("nohlsearch", 3),
+    ("normal", 4),
+    ("only", 2),
+    ("previous", 4),
+    ("print", 1),
+    ("put", 2),
+    ("quit", 1),
+    ("read", 1),
+    ("rewind", 3),
+    ("set", 2),
+    ("sign", 3),
+    ("sort", 3),
+    ("split", 2),
+    ("substitute", 1),
+    ("t", 1),
+    ("tabclose", 4),
+    ("tabedit", 4),
+    ("tabmove", 4),
+    ("tabnew", 6),
+    ("tabnext", 4),
+    ("tabonly", 4),
+    ("tabprevious", 4),
+    ("update", 2),
+    ("vglobal", 1),
+    ("vsplit", 2),
+    ("write", 1),
+    ("yank", 1),
+];
+
+/// Commands that take a `|` as part of their argument rather than as the
+/// start of another command
+const TAKES_BAR: &[&str] = &["!", "argdo", "bufdo", "global", "normal", "vglobal"];
+
+/// Expands an abbreviated command name, e.g. `noh` to `nohlsearch`
+pub fn full_name(name: &str) -> Option<&'static str> {
+    COMMANDS
+        .iter()
+        .find(|(full, shortest)| name.len() >= *shortest && full.starts_with(name))
+        .map(|(full, _)| *full)
+}
+
+impl Command {
+    pub fn parse(input: &str) -> Result<Self, String> {
+        let mut rest = input.trim_start_matches([' ', ':']);
+        let mut specs = Vec::new();
+        if let Some(after) = rest.strip_prefix('%') {
+            specs.push((
+                LineSpec {
+                    addresses: vec![Address::Number(1)],
+                    offset: 0,
+                },
+                Separator::Comma,
+            ));
+            let (mut spec, after) = parse_spec(after)?;
+            if spec.addresses.is_empty() {
+                spec.addresses.push(Address::Last);
+            }
+            specs.push((spec, Separator::Comma));
+            rest = after;
+        } else {
+            let mut separator = Separator::Comma;
+            loop {
+                let (spec, after) = parse_spec(rest.trim_start())?;
+                let empty = spec == LineSpec::default();
+                rest = after.trim_start();
+                let next = match rest.chars().next() {
+                    Some(',') => Some(Separator::Comma),
+                    Some(';') => Some(Separator::Semicolon),
+                    _ => None,
+                };
+                if !empty || next.is_some() || !specs.is_empty() {
+                    specs.push((spec, separator));
+                }
+                match next {
+                    Some(next) => {
+                        separator = next;
+                        rest = &rest[1..];
+                    }
+                    None => break,
+                }
+            }
+        }
+        let rest = rest.trim_start();
+        let length = match rest.find(|c: char| !c.is_ascii_alphabetic()) {
+            Some(0) => rest.chars().next().map_or(0, char::len_utf8),
+            Some(length) => length,
+            None => rest.len(),
+        };
+        let (name, mut argument) = rest.split_at(length);
+        let bang = argument.starts_with('!') && !name.is_empty();
+        if bang {
+            argument = &argument[1..];
+        }
+        let name = full_name(name).unwrap_or(name);
+        let argument = argument.trim_start_matches(' ');
+        // `:r!cmd` runs a command, but `:w!file` forces a write
+        let shell = matches!(name, "read" | "write") && argument.starts_with('!') || name == "read" && bang;
+        let (argument, next) = match split_bar(argument) {
+            Some((argument, next)) if !TAKES_BAR.contains(&name) && !shell => {
+                (argument.trim_end(), Some(next.to_string()))
+            }
+            _ => (argument, None),
+        };
+        Ok(Self {
+            specs,
+            name: name.to_string(),
+            bang,
+            argument: argument.to_string(),
+            next,
+        })
+    }
+
+    /// Resolves the range typed before the command, if there was one
+    pub fn range(&self, context: &impl Context) -> Result<Option<Range>, String> {
+        let Some((start, end)) = self.lines(context)? else {
+            return Ok(None);
+        };
+        Ok(Some(Range {
+            start: start.max(0) as usize,
+            end: end.max(0) as usize,
+        }))
+    }
+
+    /// The range, or the cursor line when none was given
+    pub fn range_or_current(&self, context: &impl Context) -> Result<Range, String> {
+        Ok(self
+            .range(context)?
+            .unwrap_or(Range::line(context.current_line())))
+    }
+
+    /// The last line of the range (or the cursor line) as a place to put
+    /// text below, `None` being line 0, above the first line
+    pub fn line_below(&self, context: &impl Context) -> Result<Option<usize>, String> {
+        match self.lines(context)? {
+            Some((_, end)) => Ok(usize::try_from(end).ok()),
+            None => Ok(Some(context.current_line())),
+        }
+    }
+
+    /// The first and last line of the range, -1 being line 0
+    fn lines(&self, context: &impl Context) -> Result<Option<(isize, isize)>, String> {
+        if self.specs.is_empty() {
+            return Ok(None);
+        }
+        let mut current = context.current_line() as isize;
+        let mut lines = Vec::new();
+        for (spec, separator) in &self.specs {
+            if *separator == Separator::Comma {
+                current = context.current_line() as isize;
+            }
+            current = resolve(spec, current.max(0) as usize, context)?;
+            lines.push(current);
+        }
+        let end = lines.pop().unwrap();
+        let start = lines.pop().unwrap_or(end);
+        if end < start {
+            return Err("E493: Backwards range given".to_string());
+        }
+        Ok(Some((start, end)))
+    }
+}
+
+/// Splits `text` at its first `|` that isn't escaped with a backslash
+fn split_bar(text: &str) -> Option<(&str, &str)> {
+    let mut escaped = false;
+    for (index, c) in text.char_indices() {
+        match c {
+            '|' if !escaped => return Some((&text[..index], &text[index + 1..])),
+            '\\' => escaped = !escaped,
+            _ => escaped = false,
+        }
+    }
+    None
+}
+
+/// Resolves an address given as an argument, like the destination of `:m`
+/// and `:t`, `None` being line 0, above the first line
+pub fn destination(text: &str, context: &impl Context) -> Result<Option<usize>, String> {
+    let (spec, rest) = parse_spec(text.trim())?;
+    if spec == LineSpec::default() {
+        return Err("E14: Invalid address".to_string());
+    }
+    if !rest.trim().is_empty() {
+        return Err(format!("E488: Trailing characters: {}", rest.trim()));
+    }
+    let line = resolve(&spec, context.current_line(), context)?;
+    Ok(usize::try_from(line).ok())
+}
+
+/// Resolves one line specification, an empty one (as in `:,5`) being the
+/// current line and -1 being line 0
+fn resolve(spec: &LineSpec, current: usize, context: &impl Context) -> Result<isize, String> {
+    let mut line = current as isize;
+    for address in &spec.addresses {
+        line = match address {
+            Address::Current => current as isize,
+            Address::Last => context.last_line() as isize,
+            Address::Number(number) => *number as isize - 1,
+            Address::Mark(mark) => context.mark_line(*mark).ok_or("E20: Mark not set")? as isize,
+            Address::Pattern(pattern, direction) => {
+                context.search_line(pattern, line.max(0) as usize, *direction)? as isize
+            }
+            Address::LastSearch(direction) => {
+                context.search_line("", line.max(0) as usize, *direction)? as isize
+            }
+            Address::LastSubstitute => {
+                let pattern = context
+                    .last_substitute_pattern()
+                    .ok_or("E35: No previous regular expression")?;
+                context.search_line(&pattern, line.max(0) as usize, Direction::Forward)? as isize
+            }
+        };
+    }
+    let line = line + spec.offset;
+    if line < -1 || line > context.last_line() as isize {
+        return Err("E16: Invalid range".to_string());
+    }
+    Ok(line)
+}
+
+/// Parses one line specification off the front of `input`
+pub fn parse_spec(input: &str) -> Result<(LineSpec, &str), String> {
+    let digits = |text: &str| {
+        text.find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(text.len())
+    };
+    let mut spec = LineSpec::default();
+    let mut rest = input;
+    loop {
+        let address = match rest.chars().next() {
+            Some('.') => {
+                rest = &rest[1..];
+                Address::Current
+            }
+            Some('$') => {
+                rest = &rest[1..];
+                Address::Last
+            }
+            Some('0'..='9') if spec.addresses.is_empty() && spec.offset == 0 => {
+                let length = digits(rest);
+                let number = rest[..length].parse().map_err(|_| "E16: Invalid range")?;
+                rest = &rest[length..];
+                Address::Number(number)
+            }
+            Some('\'') => {
+                let mark = rest[1..].chars().next().ok_or("E20: Mark not set")?;
+                rest = &rest[1 + mark.len_utf8()..];
+                Address::Mark(mark)
+            }
+            Some(delimiter @ ('/' | '?')) => {
+                let direction = if delimiter == '/' {
+                    Direction::Forward
+                } else {
+                    Direction::Backward
+                };
+                let (pattern, after) = split_pattern(&rest[1..], delimiter);
+                rest = after;
+                Address::Pattern(pattern, direction)
+            }
+            Some('\\') => {
+                let address = match rest[1..].chars().next() {
+                    Some('/') => Address::LastSearch(Direction::Forward),
+                    Some('?') => Address::LastSearch(Direction::Backward),
+                    Some('&') => Address::LastSubstitute,
+                    _ => return Err("E10: \\ should be followed by /, ? or &".to_string()),
+                };
+                rest = &rest[2..];
+                address
+            }
+            _ => break,
+        };
+        spec.addresses.push(address);
+    }
+    while let Some(sign @ ('+' | '-')) = rest.chars().next() {
+        let length = digits(&rest[1..]);
+        let amount: isize = if length == 0 {
+            1
+        } else {
+            rest[1..=length].parse().map_err(|_| "E16: Invalid range")?
+        };
+        spec.offset += if sign == '+' { amount } else { -amount };
+        rest = &rest[1 + length..];
+    }
+    // a number after an offset, as in `.5`, adds to it
+    if !spec.addresses.is_empty() && rest.starts_with(|c: char| c.is_ascii_digit()) {
+        let length = digits(rest);
+        spec.offset += rest[..length]
+            .parse::<isize>()
+            .map_err(|_| "E16: Invalid range")?;
+        rest = &rest[length..];
+    }
+    Ok((spec, rest))
+}
+
+/// Splits a pattern ended by an unescaped `delimiter` (or the end of the
+/// text) off `text`, turning `\{delimiter}` into the delimiter
+pub fn split_pattern(text: &str, delimiter: char) -> (String, &str) {
+    let mut pattern = String::new();
+    let mut chars = text.char_indices();
+    while let Some((index, c)) = chars.next() {
+        if c == '\\' {
+            match chars.next() {
+                Some((_, next)) if next == delimiter => pattern.push(next),
+                Some((_, next)) => {
+                    pattern.push(c);
+                    pattern.push(next);
+                }
+                None => pattern.push(c),
+            }
+        } else if c == delimiter {
+            return (pattern, &text[index + c.len_utf8()..]);
+        } else {
+            pattern.push(c);
+        }
+    }
+    (pattern, "")
+}
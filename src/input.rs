@@ -0,0 +1,119 @@
+use std::collections::VecDeque;
+
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyModifiers};
+
+/// Keys waiting to be processed before the terminal is read, such as the
+/// ones replayed by `:normal`
+#[derive(Default)]
+pub struct Typeahead {
+    keys: VecDeque<KeyEvent>,
+    /// While replaying, running out of keys aborts like `Esc` instead of
+    /// waiting for the user
+    replaying: bool,
+}
+
+impl Typeahead {
+    pub fn read(&mut self) -> KeyEvent {
+        if let Some(keypress) = self.keys.pop_front() {
+            return keypress;
+        }
+        if self.replaying {
+            return KeyEvent::new(KeyCode::Esc, KeyModifiers::NONE);
+        }
+        loop {
+            if let Ok(Event::Key(keypress)) = event::read() {
+                break keypress;
+            }
+        }
+    }
+
+    /// Puts back a key read too far, to be read again next
+    pub fn unread(&mut self, keypress: KeyEvent) {
+        self.keys.push_front(keypress);
+    }
+
+    /// The number of keys still waiting
+    pub fn pending(&self) -> usize {
+        self.keys.len()
+    }
+
+    /// Queues `keys` to be read before anything else, returning whether
+    /// keys were already being replayed
+    pub fn replay(&mut self, keys: Vec<KeyEvent>) -> bool {
+        for keypress in keys.into_iter().rev() {
+            self.keys.push_front(keypress);
+        }
+        std::mem::replace(&mut self.replaying, true)
+    }
+
+    pub fn stop_replaying(&mut self, was_replaying: bool) {
+        self.replaying = was_replaying;
+        if !was_replaying {
+            self.keys.clear();
+        }
+    }
+}
+
+/// Turns typed text into keys, understanding `<Esc>`, `<CR>`, `<Tab>`,
+/// `<BS>`, `<Del>`, the arrows, `<Home>`, `<End>`, `<lt>` and `<C-x>` as
+/// well as raw control characters
+pub fn parse_keys(text: &str) -> Vec<KeyEvent> {
+    let mut keys = Vec::new();
+    let mut rest = text;
+    while let Some(c) = rest.chars().next() {
+        if c == '<' {
+            if let Some((name, after)) = rest[1..].split_once('>') {
+                if let Some(keypress) = named_key(name) {
+                    keys.push(keypress);
+                    rest = after;
+                    continue;
+                }
+            }
+        }
+        keys.push(char_key(c));
+        rest = &rest[c.len_utf8()..];
+    }
+    keys
+}
+
+fn named_key(name: &str) -> Option<KeyEvent> {
+    let code = match name.to_ascii_lowercase().as_str() {
+        "esc" => KeyCode::Esc,
+        "cr" | "enter" | "return" => KeyCode::Enter,
+        "tab" => KeyCode::Tab,
+        "bs" => KeyCode::Backspace,
+        "del" => KeyCode::Delete,
+        "left" => KeyCode::Left,
+        "right" => KeyCode::Right,
+        "up" => KeyCode::Up,
+        "down" => KeyCode::Down,
+        "home" => KeyCode::Home,
+        "end" => KeyCode::End,
+        "lt" => KeyCode::Char('<'),
+        "space" => KeyCode::Char(' '),
+        lower => {
+            let letter = lower.strip_prefix("c-")?;
+            let mut chars = letter.chars();
+            let (Some(letter), None) = (chars.next(), chars.next()) else {
+                return None;
+            };
+            return Some(KeyEvent::new(KeyCode::Char(letter), KeyModifiers::CONTROL));
+        }
+    };
+    Some(KeyEvent::new(code, KeyModifiers::NONE))
+}
+
+fn char_key(c: char) -> KeyEvent {
+    match c {
+        '\x1b' => KeyEvent::new(KeyCode::Esc, KeyModifiers::NONE),
+        '\r' | '\n' => KeyEvent::new(KeyCode::Enter, KeyModifiers::NONE),
+        '\t' => KeyEvent::new(KeyCode::Tab, KeyModifiers::NONE),
+        '\x7f' => KeyEvent::new(KeyCode::Backspace, KeyModifiers::NONE),
+        '\x01'..='\x1a' => KeyEvent::new(
+            KeyCode::Char((b'a' + c as u8 - 1) as char),
+            KeyModifiers::CONTROL,
+        ),
+        c if c.is_uppercase() => KeyEvent::new(KeyCode::Char(c), KeyModifiers::SHIFT),
+        c => KeyEvent::new(KeyCode::Char(c), KeyModifiers::NONE),
+    }
+}
@@ -0,0 +1,117 @@
+//! The jump list that `Ctrl-O` and `Ctrl-I` go through
+
+use crate::buffer::Buffer;
+
+/// How many entries the jump list keeps
+const JUMPS: usize = 100;
+
+/// A position jumped from, kept by its buffer as an anchor so it follows
+/// the text
+#[derive(Clone, Copy)]
+struct Jump {
+    buffer: usize,
+    anchor: usize,
+}
+
+#[derive(Default)]
+pub struct JumpList {
+    jumps: Vec<Jump>,
+    /// The entry `Ctrl-O` and `Ctrl-I` last went to, `jumps.len()` after a
+    /// jump
+    index: usize,
+}
+
+impl JumpList {
+    /// Adds a position jumped from at the end, replacing older entries on
+    /// the same line
+    pub fn record(&mut self, buffers: &mut [(String, Buffer)], buffer: usize, position: (usize, usize)) {
+        self.jumps.retain(|jump| {
+            let line = buffers[jump.buffer].1.anchor(jump.anchor).map(|(line, _)| line);
+            let same_line = jump.buffer == buffer && line == Some(position.0);
+            if same_line {
+                buffers[buffer].1.remove_anchor(jump.anchor);
+            }
+            !same_line
+        });
+        if self.jumps.len() == JUMPS {
+            let oldest = self.jumps.remove(0);
+            buffers[oldest.buffer].1.remove_anchor(oldest.anchor);
+        }
+        let anchor = buffers[buffer].1.add_anchor(position);
+        self.jumps.push(Jump { buffer, anchor });
+        self.index = self.jumps.len();
+    }
+
+    /// Goes `count` entries back, or forward for a negative `count`, from
+    /// `here`, returning the buffer and position to go to
+    pub fn go(
+        &mut self,
+        buffers: &mut [(String, Buffer)],
+        here: (usize, (usize, usize)),
+        count: isize,
+    ) -> Option<(usize, (usize, usize))> {
+        let mut from = self.index;
+        if from == self.jumps.len() && count > 0 {
+            // coming back must be possible, so the current position goes at the end
+            self.record(buffers, here.0, here.1);
+            from = self.jumps.len() - 1;
+        }
+        let target = from as isize - count;
+        if target < 0 || target as usize >= self.jumps.len() {
+            return None;
+        }
+        self.index = target as usize;
+        let jump = self.jumps[self.index];
+        Some((jump.buffer, buffers[jump.buffer].1.anchor(jump.anchor)?))
+    }
+
+    /// The same entries with anchors of their own, for a new window to start
+    /// from the list of the one it was split from
+    pub fn copy(&self, buffers: &mut [(String, Buffer)]) -> Self {
+        let jumps = self
+            .jumps
+            .iter()
+            .filter_map(|jump| {
+                let buffer = &mut buffers[jump.buffer].1;
+                let position = buffer.anchor(jump.anchor)?;
+                Some(Jump {
+                    buffer: jump.buffer,
+                    anchor: buffer.add_anchor(position),
+                })
+            })
+            .collect();
+        Self { jumps, index: self.index }
+    }
+
+    /// Drops the anchors of every entry, as the window closes
+    pub fn forget(&mut self, buffers: &mut [(String, Buffer)]) {
+        for jump in self.jumps.drain(..) {
+            buffers[jump.buffer].1.remove_anchor(jump.anchor);
+        }
+        self.index = 0;
+    }
+
+    /// The lines `:jumps` shows: how far back or forward each entry is, its
+    /// position, and its text or file
+    pub fn list(&self, buffers: &[(String, Buffer)], current: usize) -> Vec<String> {
+        let mut lines = vec![" jump line  col file/text".to_string()];
+        for (index, jump) in self.jumps.iter().enumerate() {
+            let (path, buffer) = &buffers[jump.buffer];
+            let Some((line, column)) = buffer.anchor(jump.anchor) else {
+                continue;
+            };
+            let text = if jump.buffer == current {
+                buffer.lines(crate::ex::Range::line(line)).remove(0)
+            } else {
+                path.clone()
+            };
+            let marker = if index == self.index { '>' } else { ' ' };
+            let distance = index.abs_diff(self.index);
+            lines.push(format!("{marker}{distance:>3} {:>5} {column:>4} {}", line + 1, text.trim_start()));
+        }
+        if self.index == self.jumps.len() {
+            lines.push(">".to_string());
+        }
+        lines
+    }
+}
@@ -14,8 +14,18 @@ use crossterm::execute;
 use crossterm::terminal::*;
 use crossterm::QueueableCommand;
 
+mod arglist;
 mod buffer;
 mod editor;
+mod ex;
+mod input;
+mod jumplist;
+mod options;
+mod regex;
+mod register;
+mod shell;
+mod statusline;
+mod window;
 use editor::Editor;
 
 pub type Error = Box<dyn std::error::Error>;
@@ -29,7 +39,7 @@ fn main() -> Result<()> {
     stdout.queue(cursor::MoveTo(0, 0))?;
     stdout.flush()?;
 
-    let mut editor = Editor::from(env::args().into_iter().skip(1).collect::<Vec<String>>());
+    let mut editor = Editor::from(env::args().skip(1).collect::<Vec<String>>());
     editor.run()?;
     disable_raw_mode()?;
     execute!(stdout, LeaveAlternateScreen)?;
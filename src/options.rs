@@ -0,0 +1,191 @@
+/// Settings changed with `:set`
+pub struct Options {
+    pub incsearch: bool,
+    pub hlsearch: bool,
+    pub expandtab: bool,
+    pub shiftwidth: usize,
+    /// When windows have a status line: 0 never, 1 with more than one
+    /// window, 2 always
+    pub laststatus: usize,
+    pub ruler: bool,
+    pub showmode: bool,
+    /// The format of the status lines, the default one when empty
+    pub statusline: String,
+    pub number: bool,
+    pub relativenumber: bool,
+    pub numberwidth: usize,
+    /// `auto`, `yes` or `no`: when windows have a sign column
+    pub signcolumn: String,
+    /// How many columns the view scrolls sideways at least, 0 for half the
+    /// window
+    pub sidescroll: usize,
+    /// How many columns are kept left and right of the cursor
+    pub sidescrolloff: usize,
+    /// Whether long lines go on over several screen lines rather than
+    /// scrolling sideways
+    pub wrap: bool,
+    pub linebreak: bool,
+    pub breakindent: bool,
+    /// Shown at the start of the screen lines a line wraps over
+    pub showbreak: String,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            incsearch: true,
+            hlsearch: true,
+            expandtab: false,
+            shiftwidth: 8,
+            laststatus: 1,
+            ruler: true,
+            showmode: true,
+            statusline: String::new(),
+            number: false,
+            relativenumber: false,
+            numberwidth: 4,
+            signcolumn: "auto".to_string(),
+            sidescroll: 0,
+            sidescrolloff: 0,
+            wrap: true,
+            linebreak: false,
+            breakindent: false,
+            showbreak: String::new(),
+        }
+    }
+}
+
+impl Options {
+    fn flag(&mut self, name: &str) -> Option<&mut bool> {
+        match name {
+            "incsearch" | "is" => Some(&mut self.incsearch),
+            "hlsearch" | "hls" => Some(&mut self.hlsearch),
+            "expandtab" | "et" => Some(&mut self.expandtab),
+            "ruler" | "ru" => Some(&mut self.ruler),
+            "showmode" | "smd" => Some(&mut self.showmode),
+            "number" | "nu" => Some(&mut self.number),
+            "relativenumber" | "rnu" => Some(&mut self.relativenumber),
+            "wrap" => Some(&mut self.wrap),
+            "linebreak" | "lbr" => Some(&mut self.linebreak),
+            "breakindent" | "bri" => Some(&mut self.breakindent),
+            _ => None,
+        }
+    }
+
+    /// Numeric options, with their full name
+    fn number(&mut self, name: &str) -> Option<(&'static str, &mut usize)> {
+        match name {
+            "shiftwidth" | "sw" => Some(("shiftwidth", &mut self.shiftwidth)),
+            "laststatus" | "ls" => Some(("laststatus", &mut self.laststatus)),
+            "numberwidth" | "nuw" => Some(("numberwidth", &mut self.numberwidth)),
+            "sidescroll" | "ss" => Some(("sidescroll", &mut self.sidescroll)),
+            "sidescrolloff" | "siso" => Some(("sidescrolloff", &mut self.sidescrolloff)),
+            _ => None,
+        }
+    }
+
+    /// String options, with their full name
+    fn string(&mut self, name: &str) -> Option<(&'static str, &mut String)> {
+        match name {
+            "statusline" | "stl" => Some(("statusline", &mut self.statusline)),
+            "signcolumn" | "scl" => Some(("signcolumn", &mut self.signcolumn)),
+            "showbreak" | "sbr" => Some(("showbreak", &mut self.showbreak)),
+            _ => None,
+        }
+    }
+
+    /// Applies one `:set` argument such as `hls`, `nohls`, `invhls`, `hls!`,
+    /// `hls?`, `sw=4`, `sw+=2`, `sw-=2` or `stl=%f\ %m`. Queries return the
+    /// text to show.
+    pub fn set(&mut self, argument: &str) -> Result<Option<String>, String> {
+        let unknown = || format!("E518: Unknown option: {argument}");
+        if let Some(index) = argument.find(['=', ':']) {
+            let (name, operator) = match argument[..index].strip_suffix(['+', '-', '^']) {
+                Some(name) => (name, argument[name.len()..].chars().next()),
+                None => (&argument[..index], None),
+            };
+            if let Some((full, string)) = self.string(name) {
+                let value = argument[index + 1..].replace("\\ ", " ");
+                let value = match operator {
+                    Some('+') => format!("{string}{value}"),
+                    Some('-') => string.replacen(&value, "", 1),
+                    Some(_) => format!("{value}{string}"),
+                    None => value,
+                };
+                if allowed_values(full).is_some_and(|values| !values.contains(&value.as_str())) {
+                    return Err(format!("E474: Invalid argument: {argument}"));
+                }
+                *string = value;
+                return Ok(None);
+            }
+            let (_, number) = self.number(name).ok_or_else(unknown)?;
+            let value: usize = argument[index + 1..]
+                .parse()
+                .map_err(|_| format!("E521: Number required after =: {argument}"))?;
+            *number = match operator {
+                Some('+') => *number + value,
+                Some('-') => number.saturating_sub(value),
+                Some(_) => *number * value,
+                None => value,
+            };
+            return Ok(None);
+        }
+        let name = argument.strip_suffix('?').unwrap_or(argument);
+        if let Some((full, number)) = self.number(name) {
+            return Ok(Some(format!("  {full}={number}")));
+        }
+        if let Some((full, string)) = self.string(name) {
+            return Ok(Some(format!("  {full}={string}")));
+        }
+        if let Some(name) = argument.strip_suffix('?') {
+            let value = *self.flag(name).ok_or_else(unknown)?;
+            return Ok(Some(format!("{}{name}", if value { "  " } else { "no" })));
+        }
+        if let Some(name) = argument.strip_suffix('!') {
+            let flag = self.flag(name).ok_or_else(unknown)?;
+            *flag = !*flag;
+            return Ok(None);
+        }
+        if let Some(flag) = self.flag(argument) {
+            *flag = true;
+        } else if let Some(flag) = argument.strip_prefix("no").and_then(|name| self.flag(name)) {
+            *flag = false;
+        } else if let Some(flag) = argument
+            .strip_prefix("inv")
+            .and_then(|name| self.flag(name))
+        {
+            *flag = !*flag;
+        } else {
+            return Err(unknown());
+        }
+        Ok(None)
+    }
+}
+
+/// The only values a string option may take, if limited
+fn allowed_values(option: &str) -> Option<&'static [&'static str]> {
+    match option {
+        "signcolumn" => Some(&["auto", "yes", "no"]),
+        _ => None,
+    }
+}
+
+/// The arguments of `:set`, separated by white space that a backslash
+/// doesn't escape
+pub fn arguments(line: &str) -> Vec<&str> {
+    let mut arguments = Vec::new();
+    let mut start = None;
+    let mut escaped = false;
+    for (index, c) in line.char_indices() {
+        if c.is_whitespace() && !escaped {
+            if let Some(start) = start.take() {
+                arguments.push(&line[start..index]);
+            }
+        } else if start.is_none() {
+            start = Some(index);
+        }
+        escaped = c == '\\' && !escaped;
+    }
+    arguments.extend(start.map(|start| &line[start..]));
+    arguments
+}
@@ -0,0 +1,1357 @@
+//! Regular expressions with vi/Vim pattern syntax.
+//!
+//! Patterns are parsed with Vim's notion of "magic" (`\v`, `\m`, `\M`, `\V`),
+//! compiled into a small program and run by a backtracking machine over any
+//! text made of lines, so `\n` can match across line boundaries.
+
+use std::fmt;
+
+use crate::buffer::is_keyword;
+
+/// A position in the text as (line, byte)
+pub type Position = (usize, usize);
+
+/// Text that a `Regex` can run over
+pub trait Lines {
+    fn line_count(&self) -> usize;
+    fn line(&self, index: usize) -> &str;
+}
+
+impl Lines for [&str] {
+    fn line_count(&self) -> usize {
+        self.len()
+    }
+
+    fn line(&self, index: usize) -> &str {
+        self[index]
+    }
+}
+
+impl Lines for [String] {
+    fn line_count(&self) -> usize {
+        self.len()
+    }
+
+    fn line(&self, index: usize) -> &str {
+        &self[index]
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Error(String);
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Slots 0..20 hold the ten capture groups, then come `\zs`, `\ze` and the
+/// marks that keep empty loops from spinning forever
+const GROUPS: usize = 10;
+const ZS: usize = GROUPS * 2;
+const ZE: usize = ZS + 1;
+const MARKS: usize = ZE + 1;
+
+/// Upper bound on the steps of one attempt, so that pathological patterns
+/// give up instead of freezing the editor
+const STEP_LIMIT: usize = 1_000_000;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum MagicLevel {
+    VeryMagic,
+    Magic,
+    NoMagic,
+    VeryNoMagic,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Assertion {
+    LineStart,
+    LineEnd,
+    WordStart,
+    WordEnd,
+    FileStart,
+    FileEnd,
+}
+
+#[derive(Clone, Debug)]
+enum SetItem {
+    Range(char, char),
+    Class(fn(char) -> bool),
+}
+
+#[derive(Clone, Debug)]
+struct Set {
+    items: Vec<SetItem>,
+    negated: bool,
+    newline: bool,
+}
+
+#[derive(Clone, Debug)]
+enum Token {
+    Literal(char),
+    Any {
+        newline: bool,
+    },
+    Class {
+        class: char,
+        newline: bool,
+    },
+    Set(Set),
+    Newline,
+    Assert(Assertion),
+    MatchStart,
+    MatchEnd,
+    Backref(usize),
+    Open {
+        capturing: bool,
+    },
+    Close,
+    Alternate,
+    Concat,
+    Repeat {
+        min: usize,
+        max: Option<usize>,
+        greedy: bool,
+    },
+    Look {
+        behind: bool,
+        negate: bool,
+    },
+    Atomic,
+}
+
+#[derive(Clone, Debug)]
+enum Node {
+    Empty,
+    Char(char),
+    Any {
+        newline: bool,
+    },
+    Class {
+        class: char,
+        newline: bool,
+    },
+    Set(Set),
+    Newline,
+    Assert(Assertion),
+    Save(usize),
+    Backref(usize),
+    Group(Box<Node>, Option<usize>),
+    Concat(Vec<Node>),
+    Alternate(Vec<Node>),
+    Both(Vec<Node>),
+    Repeat {
+        node: Box<Node>,
+        min: usize,
+        max: Option<usize>,
+        greedy: bool,
+    },
+    Look {
+        node: Box<Node>,
+        behind: bool,
+        negate: bool,
+    },
+    Atomic(Box<Node>),
+}
+
+#[derive(Clone, Debug)]
+enum Inst {
+    Char(char),
+    Any {
+        newline: bool,
+    },
+    Class {
+        class: char,
+        newline: bool,
+    },
+    Set(Set),
+    Newline,
+    Assert(Assertion),
+    Save(usize),
+    Backref(usize),
+    Split(usize, usize),
+    Jump(usize),
+    Mark(usize),
+    Progress(usize),
+    Look {
+        program: usize,
+        behind: bool,
+        negate: bool,
+    },
+    /// Runs a program and goes on from the end of its first match, never
+    /// backtracking into it
+    Atomic(usize),
+    Match,
+}
+
+/// A compiled Vim pattern
+#[derive(Clone, Debug)]
+pub struct Regex {
+    programs: Vec<Vec<Inst>>,
+    slots: usize,
+    ignore_case: bool,
+}
+
+/// The positions a successful match recorded
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Captures {
+    slots: Vec<Option<Position>>,
+}
+
+impl Captures {
+    /// Start of the match, as moved by `\zs`
+    pub fn start(&self) -> Position {
+        self.slots[ZS].or(self.slots[0]).unwrap()
+    }
+
+    /// End of the match, as moved by `\ze`
+    pub fn end(&self) -> Position {
+        let end = self.slots[ZE].or(self.slots[1]).unwrap();
+        end.max(self.start())
+    }
+
+    /// Bounds of a capture group, group 0 being the whole match
+    pub fn group(&self, index: usize) -> Option<(Position, Position)> {
+        if index == 0 {
+            return Some((self.start(), self.end()));
+        }
+        match (self.slots.get(index * 2)?, self.slots.get(index * 2 + 1)?) {
+            (Some(start), Some(end)) if start <= end => Some((*start, *end)),
+            _ => None,
+        }
+    }
+}
+
+/// The text between two positions, lines joined with `\n`
+pub fn slice<L: Lines + ?Sized>(text: &L, from: Position, to: Position) -> String {
+    if from.0 == to.0 {
+        return text.line(from.0)[from.1..to.1].to_string();
+    }
+    let mut result = text.line(from.0)[from.1..].to_string();
+    for line in from.0 + 1..to.0 {
+        result.push('\n');
+        result.push_str(text.line(line));
+    }
+    result.push('\n');
+    result.push_str(&text.line(to.0)[..to.1]);
+    result
+}
+
+impl Regex {
+    pub fn new(pattern: &str) -> Result<Self, Error> {
+        Self::with_case(pattern, false)
+    }
+
+    /// Compiles `pattern`, ignoring case unless it says otherwise with `\C`
+    pub fn with_case(pattern: &str, ignore_case: bool) -> Result<Self, Error> {
+        let (tokens, case) = Lexer::new(pattern).lex()?;
+        let mut parser = Parser {
+            tokens,
+            index: 0,
+            groups: 0,
+        };
+        let node = parser.parse_alternation(0)?;
+        if parser.index < parser.tokens.len() {
+            return Err(Error("E55: Unmatched \\)".to_string()));
+        }
+        let mut compiler = Compiler {
+            programs: vec![Vec::new()],
+            marks: MARKS,
+        };
+        compiler.compile_program(0, &node);
+        Ok(Self {
+            programs: compiler.programs,
+            slots: compiler.marks,
+            ignore_case: case.unwrap_or(ignore_case),
+        })
+    }
+
+    /// Tries to match starting exactly at `at`
+    pub fn captures_at<L: Lines + ?Sized>(&self, text: &L, at: Position) -> Option<Captures> {
+        let mut slots = vec![None; self.slots];
+        slots[0] = Some(at);
+        let mut steps = 0;
+        let end = self.run(0, text, at, &mut slots, None, &mut steps)?;
+        slots[1] = Some(end);
+        Some(Captures { slots })
+    }
+
+    /// The first match that begins on `line` at or after byte `at`
+    pub fn captures_from<L: Lines + ?Sized>(
+        &self,
+        text: &L,
+        line: usize,
+        mut at: usize,
+    ) -> Option<Captures> {
+        let raw = text.line(line);
+        while at <= raw.len() {
+            if let Some(captures) = self.captures_at(text, (line, at)) {
+                return Some(captures);
+            }
+            at += raw[at..].chars().next().map_or(1, char::len_utf8);
+        }
+        None
+    }
+
+    /// All non-overlapping matches that begin on `line`
+    pub fn captures_in_line<L: Lines + ?Sized>(&self, text: &L, line: usize) -> Vec<Captures> {
+        let raw = text.line(line);
+        let mut found = Vec::new();
+        let mut at = 0;
+        while at <= raw.len() {
+            let next_char = at + raw[at..].chars().next().map_or(1, char::len_utf8);
+            match self.captures_at(text, (line, at)) {
+                Some(captures) => {
+                    let end = captures.slots[1].unwrap();
+                    found.push(captures);
+                    if end.0 != line {
+                        break;
+                    }
+                    at = if end.1 > at { end.1 } else { next_char };
+                }
+                None => at = next_char,
+            }
+        }
+        found
+    }
+
+    fn run<L: Lines + ?Sized>(
+        &self,
+        program: usize,
+        text: &L,
+        start: Position,
+        slots: &mut Vec<Option<Position>>,
+        must_end: Option<Position>,
+        steps: &mut usize,
+    ) -> Option<Position> {
+        let program = &self.programs[program];
+        let mut stack = vec![(0, start, slots.clone())];
+        while let Some((mut pc, mut position, mut saved)) = stack.pop() {
+            loop {
+                *steps += 1;
+                if *steps > STEP_LIMIT {
+                    return None;
+                }
+                match &program[pc] {
+                    Inst::Char(expected) => match next_char(text, position) {
+                        Some((c, next)) if self.same_char(c, *expected) => position = next,
+                        _ => break,
+                    },
+                    Inst::Any { newline } => match next_char(text, position) {
+                        Some((_, next)) => position = next,
+                        None if *newline => match next_line(text, position) {
+                            Some(next) => position = next,
+                            None => break,
+                        },
+                        None => break,
+                    },
+                    Inst::Class { class, newline } => match next_char(text, position) {
+                        Some((c, next)) if class_matches(*class, c) => position = next,
+                        None if *newline => match next_line(text, position) {
+                            Some(next) => position = next,
+                            None => break,
+                        },
+                        _ => break,
+                    },
+                    Inst::Set(set) => match next_char(text, position) {
+                        Some((c, next)) if self.set_matches(set, c) => position = next,
+                        None if set.newline => match next_line(text, position) {
+                            Some(next) => position = next,
+                            None => break,
+                        },
+                        _ => break,
+                    },
+                    Inst::Newline => match next_line(text, position) {
+                        Some(next) if next_char(text, position).is_none() => position = next,
+                        _ => break,
+                    },
+                    Inst::Assert(assertion) => {
+                        if !assertion_holds(*assertion, text, position) {
+                            break;
+                        }
+                    }
+                    Inst::Save(slot) => saved[*slot] = Some(position),
+                    Inst::Backref(group) => {
+                        let (Some(from), Some(to)) = (saved[group * 2], saved[group * 2 + 1])
+                        else {
+                            pc += 1;
+                            continue;
+                        };
+                        match self.match_backref(text, from, to, position) {
+                            Some(next) => position = next,
+                            None => break,
+                        }
+                    }
+                    Inst::Split(preferred, other) => {
+                        stack.push((*other, position, saved.clone()));
+                        pc = *preferred;
+                        continue;
+                    }
+                    Inst::Jump(target) => {
+                        pc = *target;
+                        continue;
+                    }
+                    Inst::Mark(slot) => saved[*slot] = Some(position),
+                    Inst::Progress(slot) => {
+                        if saved[*slot] == Some(position) {
+                            break;
+                        }
+                    }
+                    Inst::Look {
+                        program,
+                        behind,
+                        negate,
+                    } => {
+                        let mut inner = saved.clone();
+                        let matched = if *behind {
+                            behind_starts(text, position).into_iter().any(|from| {
+                                inner = saved.clone();
+                                self.run(*program, text, from, &mut inner, Some(position), steps)
+                                    .is_some()
+                            })
+                        } else {
+                            self.run(*program, text, position, &mut inner, None, steps)
+                                .is_some()
+                        };
+                        if matched == *negate {
+                            break;
+                        }
+                        if !negate {
+                            saved = inner;
+                        }
+                    }
+                    Inst::Atomic(program) => {
+                        let mut inner = saved.clone();
+                        match self.run(*program, text, position, &mut inner, None, steps) {
+                            Some(end) => {
+                                position = end;
+                                saved = inner;
+                            }
+                            None => break,
+                        }
+                    }
+                    Inst::Match => {
+                        if must_end.is_some_and(|end| end != position) {
+                            break;
+                        }
+                        *slots = saved;
+                        return Some(position);
+                    }
+                }
+                pc += 1;
+            }
+        }
+        None
+    }
+
+    fn same_char(&self, c: char, expected: char) -> bool {
+        c == expected || self.ignore_case && fold(c) == fold(expected)
+    }
+
+    fn set_matches(&self, set: &Set, c: char) -> bool {
+        let contains = |c: char| {
+            set.items.iter().any(|item| match item {
+                SetItem::Range(from, to) => (*from..=*to).contains(&c),
+                SetItem::Class(class) => class(c),
+            })
+        };
+        let found = contains(c)
+            || self.ignore_case
+                && (c.to_lowercase().any(contains) || c.to_uppercase().any(contains));
+        found != set.negated
+    }
+
+    fn match_backref<L: Lines + ?Sized>(
+        &self,
+        text: &L,
+        mut from: Position,
+        to: Position,
+        mut position: Position,
+    ) -> Option<Position> {
+        while from < to {
+            match next_char(text, from) {
+                Some((expected, next)) => {
+                    let (c, after) = next_char(text, position)?;
+                    if !self.same_char(c, expected) {
+                        return None;
+                    }
+                    from = next;
+                    position = after;
+                }
+                None => {
+                    if next_char(text, position).is_some() {
+                        return None;
+                    }
+                    from = next_line(text, from)?;
+                    position = next_line(text, position)?;
+                }
+            }
+        }
+        Some(position)
+    }
+}
+
+fn fold(c: char) -> char {
+    c.to_lowercase().next().unwrap_or(c)
+}
+
+fn next_char<L: Lines + ?Sized>(text: &L, (line, byte): Position) -> Option<(char, Position)> {
+    let c = text.line(line)[byte..].chars().next()?;
+    Some((c, (line, byte + c.len_utf8())))
+}
+
+fn previous_char<L: Lines + ?Sized>(text: &L, (line, byte): Position) -> Option<char> {
+    text.line(line)[..byte].chars().next_back()
+}
+
+fn next_line<L: Lines + ?Sized>(text: &L, (line, _): Position) -> Option<Position> {
+    (line + 1 < text.line_count()).then_some((line + 1, 0))
+}
+
+/// Where a look-behind may start: the current line backwards, then the
+/// previous line
+fn behind_starts<L: Lines + ?Sized>(text: &L, (line, byte): Position) -> Vec<Position> {
+    let boundaries = |line: usize, limit: usize| {
+        let raw = &text.line(line)[..limit];
+        raw.char_indices()
+            .map(move |(index, _)| (line, index))
+            .chain(std::iter::once((line, raw.len())))
+            .rev()
+    };
+    let mut starts: Vec<Position> = boundaries(line, byte).collect();
+    if let Some(previous) = line.checked_sub(1) {
+        starts.extend(boundaries(previous, text.line(previous).len()));
+    }
+    starts
+}
+
+fn assertion_holds<L: Lines + ?Sized>(assertion: Assertion, text: &L, position: Position) -> bool {
+    let before = previous_char(text, position);
+    let after = next_char(text, position).map(|(c, _)| c);
+    match assertion {
+        Assertion::LineStart => position.1 == 0,
+        Assertion::LineEnd => after.is_none(),
+        Assertion::WordStart => after.is_some_and(is_keyword) && !before.is_some_and(is_keyword),
+        Assertion::WordEnd => before.is_some_and(is_keyword) && !after.is_some_and(is_keyword),
+        Assertion::FileStart => position == (0, 0),
+        Assertion::FileEnd => after.is_none() && position.0 + 1 == text.line_count(),
+    }
+}
+
+fn class_matches(class: char, c: char) -> bool {
+    match class {
+        's' => c == ' ' || c == '\t',
+        'd' => c.is_ascii_digit(),
+        'w' => c.is_ascii_alphanumeric() || c == '_',
+        'a' => c.is_ascii_alphabetic(),
+        'l' => c.is_ascii_lowercase(),
+        'u' => c.is_ascii_uppercase(),
+        'x' => c.is_ascii_hexdigit(),
+        'o' => ('0'..='7').contains(&c),
+        'h' => c.is_ascii_alphabetic() || c == '_',
+        'k' | 'i' => is_keyword(c),
+        'f' => c.is_alphanumeric() || "/.-_+,#$%~=".contains(c),
+        'p' => !c.is_control(),
+        'K' | 'I' | 'F' | 'P' => {
+            !c.is_ascii_digit() && class_matches(class.to_ascii_lowercase(), c)
+        }
+        _ => !class_matches(class.to_ascii_lowercase(), c),
+    }
+}
+
+fn named_class(name: &str) -> Option<fn(char) -> bool> {
+    Some(match name {
+        "alnum" => |c: char| c.is_alphanumeric(),
+        "alpha" => |c: char| c.is_alphabetic(),
+        "blank" => |c: char| c == ' ' || c == '\t',
+        "cntrl" => |c: char| c.is_control(),
+        "digit" => |c: char| c.is_ascii_digit(),
+        "graph" => |c: char| !c.is_control() && !c.is_whitespace(),
+        "lower" => |c: char| c.is_lowercase(),
+        "print" => |c: char| !c.is_control(),
+        "punct" => |c: char| c.is_ascii_punctuation(),
+        "space" => |c: char| c.is_whitespace(),
+        "upper" => |c: char| c.is_uppercase(),
+        "xdigit" => |c: char| c.is_ascii_hexdigit(),
+        "ident" | "keyword" => is_keyword,
+        _ => return None,
+    })
+}
+
+struct Lexer {
+    chars: Vec<char>,
+    index: usize,
+    magic: MagicLevel,
+    tokens: Vec<Token>,
+    case: Option<bool>,
+}
+
+impl Lexer {
+    fn new(pattern: &str) -> Self {
+        Self {
+            chars: pattern.chars().collect(),
+            index: 0,
+            magic: MagicLevel::Magic,
+            tokens: Vec::new(),
+            case: None,
+        }
+    }
+
+    fn lex(mut self) -> Result<(Vec<Token>, Option<bool>), Error> {
+        while let Some(&c) = self.chars.get(self.index) {
+            self.index += 1;
+            if c == '\\' {
+                match self.chars.get(self.index) {
+                    Some(&escaped) => {
+                        self.index += 1;
+                        self.escaped(escaped)?;
+                    }
+                    None => self.tokens.push(Token::Literal('\\')),
+                }
+            } else if self.is_special(c, false) {
+                self.special(c)?;
+            } else {
+                self.tokens.push(Token::Literal(c));
+            }
+        }
+        Ok((self.tokens, self.case))
+    }
+
+    fn is_special(&self, c: char, escaped: bool) -> bool {
+        const ALWAYS_ESCAPED: &str = "()|+?={@%<>&";
+        match (self.magic, escaped) {
+            (MagicLevel::VeryMagic, escaped) => !escaped && "()|+?={@%<>.*[~^$&".contains(c),
+            (MagicLevel::Magic, false) => ".*[~^$".contains(c),
+            (MagicLevel::NoMagic, false) => "^$".contains(c),
+            (MagicLevel::VeryNoMagic, false) => false,
+            (MagicLevel::Magic, true) => ALWAYS_ESCAPED.contains(c),
+            (MagicLevel::NoMagic, true) => ALWAYS_ESCAPED.contains(c) || ".*[~".contains(c),
+            (MagicLevel::VeryNoMagic, true) => ALWAYS_ESCAPED.contains(c) || ".*[~^$".contains(c),
+        }
+    }
+
+    fn escaped(&mut self, c: char) -> Result<(), Error> {
+        if !c.is_ascii_alphanumeric() && c != '_' {
+            if self.is_special(c, true) {
+                return self.special(c);
+            }
+            self.tokens.push(Token::Literal(c));
+            return Ok(());
+        }
+        let token = match c {
+            'n' => Token::Newline,
+            't' => Token::Literal('\t'),
+            'e' => Token::Literal('\x1b'),
+            'r' => Token::Literal('\r'),
+            'b' => Token::Literal('\x08'),
+            '1'..='9' => Token::Backref(c.to_digit(10).unwrap() as usize),
+            'c' | 'C' => {
+                self.case = Some(c == 'c');
+                return Ok(());
+            }
+            'v' | 'm' | 'M' | 'V' => {
+                self.magic = match c {
+                    'v' => MagicLevel::VeryMagic,
+                    'm' => MagicLevel::Magic,
+                    'M' => MagicLevel::NoMagic,
+                    _ => MagicLevel::VeryNoMagic,
+                };
+                return Ok(());
+            }
+            'Z' => return Ok(()),
+            'z' => match self.next() {
+                Some('s') => Token::MatchStart,
+                Some('e') => Token::MatchEnd,
+                _ => return Err(Error("E68: Invalid character after \\z".to_string())),
+            },
+            '_' => match self.next() {
+                Some('.') => Token::Any { newline: true },
+                Some('^') => Token::Assert(Assertion::LineStart),
+                Some('$') => Token::Assert(Assertion::LineEnd),
+                Some('[') => match self.set()? {
+                    Some(mut set) => {
+                        set.newline = true;
+                        Token::Set(set)
+                    }
+                    None => Token::Literal('['),
+                },
+                Some(class) if "sSdDwWaAlLuUxXoOhHkKiIfFpP".contains(class) => Token::Class {
+                    class,
+                    newline: true,
+                },
+                _ => return Err(Error("E63: Invalid use of \\_".to_string())),
+            },
+            class if "sSdDwWaAlLuUxXoOhHkKiIfFpP".contains(class) => Token::Class {
+                class,
+                newline: false,
+            },
+            _ => Token::Literal(c),
+        };
+        self.tokens.push(token);
+        Ok(())
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.chars.get(self.index).copied();
+        self.index += 1;
+        c
+    }
+
+    fn at_branch_start(&self) -> bool {
+        matches!(
+            self.tokens.last(),
+            None | Some(
+                Token::Open { .. }
+                    | Token::Alternate
+                    | Token::Concat
+                    | Token::Assert(Assertion::LineStart)
+            )
+        )
+    }
+
+    fn at_branch_end(&self) -> bool {
+        let rest: String = self.chars[self.index..].iter().take(2).collect();
+        rest.is_empty()
+            || ["\\|", "\\)", "\\&", "\\n"]
+                .iter()
+                .any(|end| rest.starts_with(end))
+            || self.magic == MagicLevel::VeryMagic && rest.starts_with(['|', ')', '&'])
+    }
+
+    fn special(&mut self, c: char) -> Result<(), Error> {
+        let token = match c {
+            '(' => Token::Open { capturing: true },
+            ')' => Token::Close,
+            '|' => Token::Alternate,
+            '&' => Token::Concat,
+            '<' => Token::Assert(Assertion::WordStart),
+            '>' => Token::Assert(Assertion::WordEnd),
+            '.' => Token::Any { newline: false },
+            '~' => Token::Literal('~'),
+            '^' if self.at_branch_start() || matches!(self.tokens.last(), Some(Token::Newline)) => {
+                Token::Assert(Assertion::LineStart)
+            }
+            '$' if self.at_branch_end() => Token::Assert(Assertion::LineEnd),
+            '^' | '$' => Token::Literal(c),
+            '*' if self.at_branch_start() => Token::Literal('*'),
+            '*' => Token::Repeat {
+                min: 0,
+                max: None,
+                greedy: true,
+            },
+            '+' => Token::Repeat {
+                min: 1,
+                max: None,
+                greedy: true,
+            },
+            '=' | '?' => Token::Repeat {
+                min: 0,
+                max: Some(1),
+                greedy: true,
+            },
+            '{' => self.brace()?,
+            '@' => self.look()?,
+            '[' => match self.set()? {
+                Some(set) => Token::Set(set),
+                None => Token::Literal('['),
+            },
+            '%' => match self.next() {
+                Some('(') => Token::Open { capturing: false },
+                Some('^') => Token::Assert(Assertion::FileStart),
+                Some('$') => Token::Assert(Assertion::FileEnd),
+                Some(radix @ ('d' | 'x' | 'o' | 'u' | 'U')) => {
+                    Token::Literal(self.char_code(radix)?)
+                }
+                _ => return Err(Error("E71: Invalid character after \\%".to_string())),
+            },
+            _ => Token::Literal(c),
+        };
+        self.tokens.push(token);
+        Ok(())
+    }
+
+    /// Parses the inside of `\{n,m}`, with `-` making it non-greedy
+    fn brace(&mut self) -> Result<Token, Error> {
+        let mut inside = String::new();
+        loop {
+            match self.next() {
+                Some('}') => break,
+                Some('\\') if self.chars.get(self.index) == Some(&'}') => {
+                    self.index += 1;
+                    break;
+                }
+                Some(c) if c.is_ascii_digit() || c == ',' || c == '-' => inside.push(c),
+                _ => return Err(Error("E554: Syntax error in \\{...}".to_string())),
+            }
+        }
+        let greedy = !inside.starts_with('-');
+        let inside = inside.trim_start_matches('-');
+        let number = |text: &str| -> Result<Option<usize>, Error> {
+            if text.is_empty() {
+                Ok(None)
+            } else {
+                text.parse()
+                    .map(Some)
+                    .map_err(|_| Error("E554: Syntax error in \\{...}".to_string()))
+            }
+        };
+        let (min, max) = match inside.split_once(',') {
+            Some((min, max)) => (number(min)?.unwrap_or(0), number(max)?),
+            None => match number(inside)? {
+                Some(exact) => (exact, Some(exact)),
+                None => (0, None),
+            },
+        };
+        if max.is_some_and(|max| max < min) {
+            return Ok(Token::Repeat {
+                min: max.unwrap(),
+                max: Some(min),
+                greedy,
+            });
+        }
+        Ok(Token::Repeat { min, max, greedy })
+    }
+
+    /// Parses what follows `\@`: `>`, `=`, `!`, `<=` or `<!`, with an
+    /// optional byte limit that is accepted and ignored
+    fn look(&mut self) -> Result<Token, Error> {
+        while self.chars.get(self.index).is_some_and(char::is_ascii_digit) {
+            self.index += 1;
+        }
+        let behind = self.chars.get(self.index) == Some(&'<');
+        if behind {
+            self.index += 1;
+        }
+        match self.next() {
+            Some('=') => Ok(Token::Look {
+                behind,
+                negate: false,
+            }),
+            Some('!') => Ok(Token::Look {
+                behind,
+                negate: true,
+            }),
+            Some('>') if !behind => Ok(Token::Atomic),
+            _ => Err(Error("E869: Unknown operator after \\@".to_string())),
+        }
+    }
+
+    fn char_code(&mut self, radix: char) -> Result<char, Error> {
+        let (base, length) = match radix {
+            'd' => (10, 10),
+            'o' => (8, 11),
+            'x' => (16, 2),
+            'u' => (16, 4),
+            _ => (16, 8),
+        };
+        let mut digits = String::new();
+        while digits.len() < length {
+            match self.chars.get(self.index) {
+                Some(c) if c.is_digit(base) => {
+                    digits.push(*c);
+                    self.index += 1;
+                }
+                _ => break,
+            }
+        }
+        u32::from_str_radix(&digits, base)
+            .ok()
+            .and_then(char::from_u32)
+            .ok_or_else(|| Error("E678: Invalid character after \\%[dxouU]".to_string()))
+    }
+
+    /// Parses a `[...]` collection, or returns `None` if it is not closed so
+    /// that the `[` is taken literally
+    fn set(&mut self) -> Result<Option<Set>, Error> {
+        let start = self.index;
+        let mut set = Set {
+            items: Vec::new(),
+            negated: false,
+            newline: false,
+        };
+        if self.chars.get(self.index) == Some(&'^') {
+            set.negated = true;
+            self.index += 1;
+        }
+        if self.chars.get(self.index) == Some(&']') {
+            set.items.push(SetItem::Range(']', ']'));
+            self.index += 1;
+        }
+        loop {
+            let Some(c) = self.next() else {
+                self.index = start;
+                return Ok(None);
+            };
+            let from = match c {
+                ']' => return Ok(Some(set)),
+                '[' if self.chars.get(self.index) == Some(&':') => {
+                    let rest: String = self.chars[self.index + 1..].iter().collect();
+                    match rest.split_once(":]") {
+                        Some((name, _)) => match named_class(name) {
+                            Some(class) => {
+                                self.index += name.chars().count() + 3;
+                                set.items.push(SetItem::Class(class));
+                                continue;
+                            }
+                            None => '[',
+                        },
+                        None => '[',
+                    }
+                }
+                '\\' => match self.next() {
+                    Some('n') => {
+                        set.newline = true;
+                        continue;
+                    }
+                    Some('e') => '\x1b',
+                    Some('t') => '\t',
+                    Some('r') => '\r',
+                    Some('b') => '\x08',
+                    Some(radix @ ('d' | 'x' | 'o' | 'u' | 'U')) => self.char_code(radix)?,
+                    Some(escaped @ ('\\' | ']' | '^' | '-')) => escaped,
+                    Some(other) => {
+                        set.items.push(SetItem::Range('\\', '\\'));
+                        other
+                    }
+                    None => '\\',
+                },
+                c => c,
+            };
+            if self.chars.get(self.index) == Some(&'-')
+                && self.chars.get(self.index + 1).is_some_and(|c| *c != ']')
+            {
+                self.index += 1;
+                let mut to = self.next().unwrap();
+                if to == '\\' {
+                    to = self.next().unwrap_or('\\');
+                }
+                if to < from {
+                    return Err(Error("E944: Reverse range in character class".to_string()));
+                }
+                set.items.push(SetItem::Range(from, to));
+            } else {
+                set.items.push(SetItem::Range(from, from));
+            }
+        }
+    }
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    index: usize,
+    groups: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.index)
+    }
+
+    fn parse_alternation(&mut self, depth: usize) -> Result<Node, Error> {
+        let mut branches = vec![self.parse_branch(depth)?];
+        while let Some(Token::Alternate) = self.peek() {
+            self.index += 1;
+            branches.push(self.parse_branch(depth)?);
+        }
+        Ok(if branches.len() == 1 {
+            branches.pop().unwrap()
+        } else {
+            Node::Alternate(branches)
+        })
+    }
+
+    fn parse_branch(&mut self, depth: usize) -> Result<Node, Error> {
+        let mut concats = vec![self.parse_concat(depth)?];
+        while let Some(Token::Concat) = self.peek() {
+            self.index += 1;
+            concats.push(self.parse_concat(depth)?);
+        }
+        Ok(if concats.len() == 1 {
+            concats.pop().unwrap()
+        } else {
+            Node::Both(concats)
+        })
+    }
+
+    fn parse_concat(&mut self, depth: usize) -> Result<Node, Error> {
+        let mut pieces = Vec::new();
+        loop {
+            match self.peek() {
+                None | Some(Token::Alternate | Token::Concat) => break,
+                Some(Token::Close) if depth > 0 => break,
+                Some(Token::Close) => return Err(Error("E55: Unmatched \\)".to_string())),
+                _ => pieces.push(self.parse_piece(depth)?),
+            }
+        }
+        Ok(match pieces.len() {
+            0 => Node::Empty,
+            1 => pieces.pop().unwrap(),
+            _ => Node::Concat(pieces),
+        })
+    }
+
+    fn parse_piece(&mut self, depth: usize) -> Result<Node, Error> {
+        let mut node = self.parse_atom(depth)?;
+        loop {
+            match self.peek() {
+                Some(Token::Repeat { min, max, greedy }) => {
+                    node = Node::Repeat {
+                        node: Box::new(node),
+                        min: *min,
+                        max: *max,
+                        greedy: *greedy,
+                    };
+                }
+                Some(Token::Look { behind, negate }) => {
+                    node = Node::Look {
+                        node: Box::new(node),
+                        behind: *behind,
+                        negate: *negate,
+                    };
+                }
+                Some(Token::Atomic) => node = Node::Atomic(Box::new(node)),
+                _ => break,
+            }
+            self.index += 1;
+        }
+        Ok(node)
+    }
+
+    fn parse_atom(&mut self, depth: usize) -> Result<Node, Error> {
+        let token = self.tokens[self.index].clone();
+        self.index += 1;
+        Ok(match token {
+            Token::Literal(c) => Node::Char(c),
+            Token::Any { newline } => Node::Any { newline },
+            Token::Class { class, newline } => Node::Class { class, newline },
+            Token::Set(set) => Node::Set(set),
+            Token::Newline => Node::Newline,
+            Token::Assert(assertion) => Node::Assert(assertion),
+            Token::MatchStart => Node::Save(ZS),
+            Token::MatchEnd => Node::Save(ZE),
+            Token::Backref(group) => {
+                if group > self.groups {
+                    return Err(Error("E65: Illegal back reference".to_string()));
+                }
+                Node::Backref(group)
+            }
+            Token::Open { capturing } => {
+                let group = if capturing {
+                    self.groups += 1;
+                    if self.groups >= GROUPS {
+                        return Err(Error("E51: Too many \\(".to_string()));
+                    }
+                    Some(self.groups)
+                } else {
+                    None
+                };
+                let inner = self.parse_alternation(depth + 1)?;
+                match self.peek() {
+                    Some(Token::Close) => self.index += 1,
+                    _ => return Err(Error("E54: Unmatched \\(".to_string())),
+                }
+                Node::Group(Box::new(inner), group)
+            }
+            Token::Repeat { .. } | Token::Look { .. } | Token::Atomic => {
+                return Err(Error("E64: Multi follows nothing".to_string()))
+            }
+            Token::Close | Token::Alternate | Token::Concat => unreachable!(),
+        })
+    }
+}
+
+struct Compiler {
+    programs: Vec<Vec<Inst>>,
+    marks: usize,
+}
+
+impl Compiler {
+    fn compile_program(&mut self, program: usize, node: &Node) {
+        self.compile(program, node);
+        self.programs[program].push(Inst::Match);
+    }
+
+    fn emit(&mut self, program: usize, inst: Inst) -> usize {
+        self.programs[program].push(inst);
+        self.programs[program].len() - 1
+    }
+
+    fn here(&self, program: usize) -> usize {
+        self.programs[program].len()
+    }
+
+    fn patch(&mut self, program: usize, at: usize, target: usize) {
+        match &mut self.programs[program][at] {
+            Inst::Split(preferred, other) => {
+                if *preferred == usize::MAX {
+                    *preferred = target;
+                } else {
+                    *other = target;
+                }
+            }
+            Inst::Jump(to) => *to = target,
+            _ => unreachable!(),
+        }
+    }
+
+    /// Compiles `node` into a program of its own, returning its index
+    fn subprogram(&mut self, node: &Node) -> usize {
+        let inner = self.programs.len();
+        self.programs.push(Vec::new());
+        self.compile_program(inner, node);
+        inner
+    }
+
+    fn look(&mut self, program: usize, node: &Node, behind: bool, negate: bool) {
+        let inner = self.subprogram(node);
+        self.emit(
+            program,
+            Inst::Look {
+                program: inner,
+                behind,
+                negate,
+            },
+        );
+    }
+
+    /// Emits a split whose continuation is patched later: the body right
+    /// after it, preferred when greedy
+    fn split(&mut self, program: usize, greedy: bool) -> usize {
+        let next = self.here(program) + 1;
+        if greedy {
+            self.emit(program, Inst::Split(next, usize::MAX))
+        } else {
+            self.emit(program, Inst::Split(usize::MAX, next))
+        }
+    }
+
+    fn compile(&mut self, program: usize, node: &Node) {
+        match node {
+            Node::Empty => (),
+            Node::Char(c) => {
+                self.emit(program, Inst::Char(*c));
+            }
+            Node::Any { newline } => {
+                self.emit(program, Inst::Any { newline: *newline });
+            }
+            Node::Class { class, newline } => {
+                self.emit(
+                    program,
+                    Inst::Class {
+                        class: *class,
+                        newline: *newline,
+                    },
+                );
+            }
+            Node::Set(set) => {
+                self.emit(program, Inst::Set(set.clone()));
+            }
+            Node::Newline => {
+                self.emit(program, Inst::Newline);
+            }
+            Node::Assert(assertion) => {
+                self.emit(program, Inst::Assert(*assertion));
+            }
+            Node::Save(slot) => {
+                self.emit(program, Inst::Save(*slot));
+            }
+            Node::Backref(group) => {
+                self.emit(program, Inst::Backref(*group));
+            }
+            Node::Group(inner, group) => {
+                if let Some(group) = group {
+                    self.emit(program, Inst::Save(group * 2));
+                }
+                self.compile(program, inner);
+                if let Some(group) = group {
+                    self.emit(program, Inst::Save(group * 2 + 1));
+                }
+            }
+            Node::Concat(nodes) => {
+                for node in nodes {
+                    self.compile(program, node);
+                }
+            }
+            Node::Alternate(branches) => {
+                let mut jumps = Vec::new();
+                for (index, branch) in branches.iter().enumerate() {
+                    if index + 1 == branches.len() {
+                        self.compile(program, branch);
+                    } else {
+                        let split = self.split(program, true);
+                        self.compile(program, branch);
+                        jumps.push(self.emit(program, Inst::Jump(usize::MAX)));
+                        let next = self.here(program);
+                        self.patch(program, split, next);
+                    }
+                }
+                let end = self.here(program);
+                for jump in jumps {
+                    self.patch(program, jump, end);
+                }
+            }
+            Node::Both(concats) => {
+                let (last, rest) = concats.split_last().unwrap();
+                for concat in rest {
+                    self.look(program, concat, false, false);
+                }
+                self.compile(program, last);
+            }
+            Node::Repeat {
+                node,
+                min,
+                max,
+                greedy,
+            } => {
+                for _ in 0..*min {
+                    self.compile(program, node);
+                }
+                match max {
+                    Some(max) => {
+                        let mut splits = Vec::new();
+                        for _ in *min..*max {
+                            splits.push(self.split(program, *greedy));
+                            self.compile(program, node);
+                        }
+                        let end = self.here(program);
+                        for split in splits {
+                            self.patch(program, split, end);
+                        }
+                    }
+                    None => {
+                        let mark = self.marks;
+                        self.marks += 1;
+                        let start = self.emit(program, Inst::Mark(mark));
+                        let split = self.split(program, *greedy);
+                        self.compile(program, node);
+                        self.emit(program, Inst::Progress(mark));
+                        self.emit(program, Inst::Jump(start));
+                        let end = self.here(program);
+                        self.patch(program, split, end);
+                    }
+                }
+            }
+            Node::Look {
+                node,
+                behind,
+                negate,
+            } => self.look(program, node, *behind, *negate),
+            Node::Atomic(node) => {
+                let inner = self.subprogram(node);
+                self.emit(program, Inst::Atomic(inner));
+            }
+        }
+    }
+}
+
+/// Escapes `text` so that it matches literally in a magic pattern, also
+/// escaping `delimiter`
+pub fn escape(text: &str, delimiter: char) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if c == delimiter || "\\.*~[]^$".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The bounds of the first match of `pattern` in `lines`
+    fn find(pattern: &str, lines: &[&str]) -> Option<(Position, Position)> {
+        let regex = Regex::new(pattern).unwrap();
+        (0..lines.len())
+            .find_map(|line| regex.captures_from(lines, line, 0))
+            .map(|captures| (captures.start(), captures.end()))
+    }
+
+    /// The text of the first match of `pattern` in `line`
+    fn matched(pattern: &str, line: &str) -> Option<String> {
+        let lines = [line];
+        find(pattern, &lines).map(|(start, end)| slice(&lines[..], start, end))
+    }
+
+    #[test]
+    fn magic_levels() {
+        assert_eq!(matched("a.c", "abc").as_deref(), Some("abc"));
+        assert_eq!(matched("a\\.c", "abc"), None);
+        assert_eq!(matched("\\v(ab)+", "xababy").as_deref(), Some("abab"));
+        assert_eq!(matched("\\va{2}", "aaa").as_deref(), Some("aa"));
+        assert_eq!(matched("\\Ma.c", "abc"), None);
+        assert_eq!(matched("\\Ma\\.c", "abc").as_deref(), Some("abc"));
+        assert_eq!(matched("\\Va*b", "aab a*b").as_deref(), Some("a*b"));
+        assert_eq!(find("\\V\\^b", &["ab"]), None);
+    }
+
+    #[test]
+    fn word_boundaries() {
+        assert_eq!(find("\\<is\\>", &["this is"]), Some(((0, 5), (0, 7))));
+        assert_eq!(find("\\<is\\>", &["this isle"]), None);
+    }
+
+    #[test]
+    fn counts() {
+        assert_eq!(matched("a\\{2,3}", "aaaa").as_deref(), Some("aaa"));
+        assert_eq!(matched("a\\{2}", "aaaa").as_deref(), Some("aa"));
+        assert_eq!(matched("a\\{,2}", "aaaa").as_deref(), Some("aa"));
+        assert_eq!(matched("a\\{-1,}", "aaaa").as_deref(), Some("a"));
+        assert_eq!(matched("a\\{-}", "aaaa").as_deref(), Some(""));
+        assert_eq!(matched("a\\{3,1}", "aaaa").as_deref(), Some("aaa"));
+    }
+
+    #[test]
+    fn case() {
+        assert_eq!(matched("\\cabc", "ABC").as_deref(), Some("ABC"));
+        assert_eq!(matched("abc", "ABC"), None);
+        let regex = Regex::with_case("\\Cabc", true).unwrap();
+        assert_eq!(regex.captures_from(&["ABC"][..], 0, 0), None);
+        let regex = Regex::with_case("abc", true).unwrap();
+        assert!(regex.captures_from(&["ABC"][..], 0, 0).is_some());
+    }
+
+    #[test]
+    fn match_start_and_end() {
+        assert_eq!(find("foo\\zsbar", &["foobar"]), Some(((0, 3), (0, 6))));
+        assert_eq!(find("foo\\zebar", &["foobar"]), Some(((0, 0), (0, 3))));
+        assert_eq!(find("foo\\zebar", &["foobaz"]), None);
+    }
+
+    #[test]
+    fn newlines() {
+        assert_eq!(find("a\\nb", &["xa", "by"]), Some(((0, 1), (1, 1))));
+        assert_eq!(find("a\\_sb", &["a", "b"]), Some(((0, 0), (1, 1))));
+        assert_eq!(find("a\\nb", &["a", "xb"]), None);
+        assert_eq!(find("a$\\n^b", &["a", "b"]), Some(((0, 0), (1, 1))));
+    }
+
+    #[test]
+    fn lookarounds() {
+        assert_eq!(find("foo\\(bar\\)\\@=", &["foobar"]), Some(((0, 0), (0, 3))));
+        assert_eq!(find("foo\\(bar\\)\\@!", &["foobar foobaz"]), Some(((0, 7), (0, 10))));
+        assert_eq!(find("\\(foo\\)\\@<=bar", &["xbar foobar"]), Some(((0, 8), (0, 11))));
+        assert_eq!(find("\\(foo\\)\\@<!bar", &["foobar xbar"]), Some(((0, 8), (0, 11))));
+    }
+
+    #[test]
+    fn atomic_groups() {
+        assert_eq!(matched("\\(a*\\)\\@>a", "aaa"), None);
+        assert_eq!(matched("\\(a*\\)\\@>b", "aab").as_deref(), Some("aab"));
+        assert_eq!(matched("\\v(a|ab)@>c", "abc"), None);
+    }
+
+    #[test]
+    fn errors() {
+        assert!(Regex::new("a\\@x").unwrap_err().to_string().starts_with("E869"));
+        assert!(Regex::new("\\(a").unwrap_err().to_string().starts_with("E54"));
+        assert!(Regex::new("a\\|*b").is_ok());
+        assert!(Regex::new("\\@>a").unwrap_err().to_string().starts_with("E64"));
+    }
+}
@@ -0,0 +1,98 @@
+use std::collections::HashMap;
+
+/// How the text of a register goes back into a buffer
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum RegisterKind {
+    Charwise,
+    Linewise,
+    /// A rectangle, one string per line
+    Blockwise,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Register {
+    pub lines: Vec<String>,
+    pub kind: RegisterKind,
+}
+
+impl Register {
+    pub fn linewise(lines: Vec<String>) -> Self {
+        Self {
+            lines,
+            kind: RegisterKind::Linewise,
+        }
+    }
+}
+
+/// The unnamed, numbered, small delete and named registers
+#[derive(Default)]
+pub struct Registers {
+    registers: HashMap<char, Register>,
+    /// The register the unnamed one `"` currently points to
+    unnamed: Option<char>,
+}
+
+impl Registers {
+    pub fn is_valid(name: char) -> bool {
+        name.is_ascii_alphanumeric() || "\"-_".contains(name)
+    }
+
+    pub fn get(&self, name: char) -> Option<&Register> {
+        match name {
+            '"' => self.registers.get(&self.unnamed?),
+            name => self.registers.get(&name.to_ascii_lowercase()),
+        }
+    }
+
+    /// Stores yanked text in `name`, or in `"0` when no register is given
+    pub fn yank(&mut self, name: Option<char>, register: Register) {
+        match name {
+            None | Some('"') => self.store('0', register),
+            Some(name) => self.store(name, register),
+        }
+    }
+
+    /// Stores deleted text in `name`, or else shifts it into `"1` (or `"-`
+    /// for deletes within a line)
+    pub fn delete(&mut self, name: Option<char>, register: Register) {
+        match name {
+            None | Some('"') if register.kind == RegisterKind::Linewise || register.lines.len() > 1 => {
+                for number in (1..9).rev() {
+                    let from = char::from_digit(number, 10).unwrap();
+                    let to = char::from_digit(number + 1, 10).unwrap();
+                    if let Some(shifted) = self.registers.remove(&from) {
+                        self.registers.insert(to, shifted);
+                    }
+                }
+                self.store('1', register);
+            }
+            None | Some('"') => self.store('-', register),
+            Some(name) => self.store(name, register),
+        }
+    }
+
+    fn store(&mut self, name: char, register: Register) {
+        if name == '_' {
+            return;
+        }
+        let lower = name.to_ascii_lowercase();
+        match self.registers.get_mut(&lower) {
+            Some(existing) if name.is_ascii_uppercase() => {
+                if existing.kind == RegisterKind::Linewise || register.kind == RegisterKind::Linewise {
+                    existing.lines.extend(register.lines);
+                    existing.kind = RegisterKind::Linewise;
+                } else {
+                    let mut lines = register.lines.into_iter();
+                    if let (Some(last), Some(first)) = (existing.lines.last_mut(), lines.next()) {
+                        last.push_str(&first);
+                    }
+                    existing.lines.extend(lines);
+                }
+            }
+            _ => {
+                self.registers.insert(lower, register);
+            }
+        }
+        self.unnamed = Some(lower);
+    }
+}
@@ -0,0 +1,103 @@
+//! Running external commands for `!`, `:!`, `:r !` and `:w !`
+
+use std::io::{stdout, Read, Write};
+use std::process::{Command, ExitStatus, Stdio};
+use std::{env, thread};
+
+use crossterm::execute;
+use crossterm::terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen};
+
+use crate::input::Typeahead;
+
+pub type Error = Box<dyn std::error::Error>;
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Gives the terminal back to its normal state until dropped
+struct Suspended {
+    screen: bool,
+}
+
+impl Suspended {
+    /// Leaves raw mode, and the alternate screen too when `screen`
+    fn new(screen: bool) -> Result<Self> {
+        if screen {
+            execute!(stdout(), LeaveAlternateScreen)?;
+        }
+        disable_raw_mode()?;
+        Ok(Self { screen })
+    }
+}
+
+impl Drop for Suspended {
+    fn drop(&mut self) {
+        enable_raw_mode().ok();
+        if self.screen {
+            execute!(stdout(), EnterAlternateScreen).ok();
+        }
+    }
+}
+
+fn shell(command: &str) -> Command {
+    let mut shell = Command::new(env::var("SHELL").unwrap_or_else(|_| "sh".to_string()));
+    shell.arg("-c").arg(command);
+    shell
+}
+
+/// Runs `command` with `input` as its standard input, returning what it
+/// wrote to its standard output and error, line by line
+pub fn filter(command: &str, input: Option<Vec<String>>) -> Result<(Vec<String>, ExitStatus)> {
+    let _suspended = Suspended::new(false)?;
+    let mut child = shell(&format!("({command}) 2>&1"))
+        .stdin(if input.is_some() { Stdio::piped() } else { Stdio::null() })
+        .stdout(Stdio::piped())
+        .spawn()?;
+    let writer = child.stdin.take().zip(input).map(|(mut stdin, lines)| {
+        thread::spawn(move || {
+            for line in lines {
+                if writeln!(stdin, "{line}").is_err() {
+                    break;
+                }
+            }
+        })
+    });
+    let mut output = String::new();
+    if let Some(mut stdout) = child.stdout.take() {
+        stdout.read_to_string(&mut output)?;
+    }
+    let status = child.wait()?;
+    if let Some(writer) = writer {
+        writer.join().ok();
+    }
+    Ok((output.lines().map(str::to_string).collect(), status))
+}
+
+/// Runs `command` on the normal screen, feeding it `input` if there is
+/// some, and waits for a key before coming back
+pub fn run(command: &str, input: Option<Vec<String>>, typeahead: &mut Typeahead) -> Result<()> {
+    let _suspended = Suspended::new(true)?;
+    let mut stdout = stdout();
+    write!(stdout, "\r\n")?;
+    stdout.flush()?;
+    let status = match input {
+        Some(lines) => {
+            let mut child = shell(command).stdin(Stdio::piped()).spawn()?;
+            if let Some(mut stdin) = child.stdin.take() {
+                for line in lines {
+                    if writeln!(stdin, "{line}").is_err() {
+                        break;
+                    }
+                }
+            }
+            child.wait()?
+        }
+        None => shell(command).status()?,
+    };
+    if let Some(code) = status.code().filter(|code| *code != 0) {
+        write!(stdout, "\nshell returned {code}\n")?;
+    }
+    write!(stdout, "\nPress ENTER or type command to continue")?;
+    stdout.flush()?;
+    enable_raw_mode()?;
+    typeahead.read();
+    Ok(())
+}
@@ -0,0 +1,117 @@
+//! The status line under a window and the ruler, drawn from a format like
+//! Vim's 'statusline'
+
+use std::iter::Peekable;
+use std::str::Chars;
+
+/// What is shown when 'statusline' is empty
+pub const DEFAULT: &str = "%f %m%r%=%{&fileencoding} [%{&fileformat}]  %l,%c%V  %P";
+
+/// What the ruler shows on the last line, without a status line
+pub const RULER: &str = "%l,%c%V%=%P";
+
+/// How wide the ruler is
+pub const RULER_WIDTH: usize = 18;
+
+/// What the items of a format stand for
+pub struct Status<'a> {
+    pub path: &'a str,
+    pub modified: bool,
+    pub readonly: bool,
+    /// The cursor line, from 1
+    pub line: usize,
+    pub lines: usize,
+    /// The byte of the cursor, from 1, 0 on an empty line
+    pub column: usize,
+    /// The screen column of the cursor, from 1
+    pub virtual_column: usize,
+    /// The first line shown and how many are
+    pub top: usize,
+    pub height: usize,
+    pub fileformat: &'a str,
+    pub fileencoding: &'a str,
+}
+
+impl Status<'_> {
+    /// `Top`, `Bot`, `All` or how far down the window is, in percent
+    fn position(&self) -> String {
+        let above = self.top;
+        let below = self.lines.saturating_sub(self.top + self.height);
+        match (above, below) {
+            (0, 0) => "All".to_string(),
+            (0, _) => "Top".to_string(),
+            (_, 0) => "Bot".to_string(),
+            _ => format!("{}%", above * 100 / (above + below)),
+        }
+    }
+
+    /// `%{&name}`: the value of a buffer option
+    fn option(&self, name: &str) -> String {
+        match name {
+            "fileformat" | "ff" => self.fileformat.to_string(),
+            "fileencoding" | "fenc" => self.fileencoding.to_string(),
+            "modified" | "mod" => u8::from(self.modified).to_string(),
+            "readonly" | "ro" => u8::from(self.readonly).to_string(),
+            _ => String::new(),
+        }
+    }
+}
+
+/// Expands the items of `format`: `%f` file, `%t` its name alone, `%m`
+/// and `%M` modified, `%r` and `%R` read-only, `%l` line, `%L` lines, `%c`
+/// byte and `%v` screen column, `%V` the screen column when it differs,
+/// `%p` percent through the file, `%P` through the window, `%{&option}`,
+/// `%%`, and `%=` after which the text goes on the right of a line `width`
+/// wide
+pub fn render(format: &str, status: &Status, width: usize) -> String {
+    let mut left = String::new();
+    let mut right: Option<String> = None;
+    let mut chars = format.chars().peekable();
+    while let Some(c) = chars.next() {
+        let item = match (c, chars.next_if(|_| c == '%')) {
+            ('%', Some('=')) if right.is_none() => {
+                right = Some(String::new());
+                continue;
+            }
+            ('%', Some(item)) => expand(item, &mut chars, status),
+            (c, _) => c.to_string(),
+        };
+        right.as_mut().unwrap_or(&mut left).push_str(&item);
+    }
+    let right = right.unwrap_or_default();
+    let right: String = right.chars().take(width).collect();
+    let room = width - right.chars().count();
+    let left: String = left.chars().take(room).collect();
+    let padding = room - left.chars().count();
+    format!("{left}{}{right}", " ".repeat(padding))
+}
+
+/// The text of `%` followed by `item`, taking the expression of `%{...}` from
+/// `chars`
+fn expand(item: char, chars: &mut Peekable<Chars>, status: &Status) -> String {
+    match item {
+        'f' => status.path.to_string(),
+        't' => status.path.rsplit('/').next().unwrap_or_default().to_string(),
+        'm' if status.modified => "[+]".to_string(),
+        'M' if status.modified => ",+".to_string(),
+        'r' if status.readonly => "[RO]".to_string(),
+        'R' if status.readonly => ",RO".to_string(),
+        'm' | 'M' | 'r' | 'R' | '<' | '=' => String::new(),
+        'l' => status.line.to_string(),
+        'L' => status.lines.to_string(),
+        'c' => status.column.to_string(),
+        'v' => status.virtual_column.to_string(),
+        'V' if status.virtual_column != status.column => format!("-{}", status.virtual_column),
+        'V' => String::new(),
+        'p' => (status.line * 100 / status.lines.max(1)).to_string(),
+        'P' => status.position(),
+        '{' => {
+            let expression: String = chars.by_ref().take_while(|c| *c != '}').collect();
+            match expression.strip_prefix('&') {
+                Some(name) => status.option(name),
+                None => String::new(),
+            }
+        }
+        other => other.to_string(),
+    }
+}
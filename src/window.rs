@@ -0,0 +1,356 @@
+//! Windows, views onto the buffers, and how they share the screen
+
+use crate::buffer::Buffer;
+use crate::jumplist::JumpList;
+
+/// A rectangle of the screen, in rows and columns
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct Area {
+    pub top: usize,
+    pub left: usize,
+    pub height: usize,
+    pub width: usize,
+}
+
+impl Area {
+    pub fn contains(&self, (row, column): (usize, usize)) -> bool {
+        (self.top..self.top + self.height).contains(&row) && (self.left..self.left + self.width).contains(&column)
+    }
+}
+
+/// A view onto a buffer. The current window's cursor and scroll offset are
+/// those of its buffer, and its jump list the editor's; the others keep
+/// theirs, the view as anchors in the buffer so that it follows the text as
+/// it changes.
+pub struct Window {
+    pub buffer: usize,
+    /// The anchors of the cursor and of the top line
+    view: Option<(usize, usize)>,
+    /// The first screen column shown
+    left: usize,
+    jumps: JumpList,
+}
+
+impl Window {
+    pub fn new(buffer: usize) -> Self {
+        Self {
+            buffer,
+            view: None,
+            left: 0,
+            jumps: JumpList::default(),
+        }
+    }
+
+    /// Keeps the view of `buffer`, number `index`, and the jump list as
+    /// another window becomes the current one
+    pub fn leave(&mut self, index: usize, buffer: &mut Buffer, jumps: &mut JumpList) {
+        self.jumps = std::mem::take(jumps);
+        let (cursor, offset) = buffer.view();
+        self.buffer = index;
+        self.view = Some((buffer.add_anchor(cursor), buffer.add_anchor((offset, 0))));
+        self.left = buffer.left_column();
+    }
+
+    /// Gives the buffer back the view kept by `leave`, and `jumps` the jump
+    /// list, returning the index of the buffer
+    pub fn enter(&mut self, buffers: &mut [(String, Buffer)], jumps: &mut JumpList) -> usize {
+        *jumps = std::mem::take(&mut self.jumps);
+        if let Some(((line, x), top)) = self.view(&buffers[self.buffer].1) {
+            self.forget(buffers);
+            let buffer = &mut buffers[self.buffer].1;
+            let x = x.min(buffer.line_length(line).saturating_sub(1));
+            buffer.restore_view(((line, x), top));
+            buffer.set_left_column(self.left);
+        }
+        self.buffer
+    }
+
+    /// The cursor and top line kept by `leave`
+    pub fn view(&self, buffer: &Buffer) -> Option<((usize, usize), usize)> {
+        let (cursor, top) = self.view?;
+        Some((buffer.anchor(cursor)?, buffer.anchor(top)?.0))
+    }
+
+    /// The first screen column shown, kept by `leave`
+    pub fn left(&self) -> usize {
+        self.left
+    }
+
+    /// Drops the anchors of the view and of the jump list, as the window
+    /// closes
+    pub fn forget(&mut self, buffers: &mut [(String, Buffer)]) {
+        if let Some((cursor, top)) = self.view.take() {
+            buffers[self.buffer].1.remove_anchor(cursor);
+            buffers[self.buffer].1.remove_anchor(top);
+        }
+        self.jumps.forget(buffers);
+    }
+}
+
+/// A tab page: windows and their layout, put aside while another tab page
+/// is the current one
+pub struct TabPage {
+    pub windows: Vec<Window>,
+    pub window: usize,
+    pub layout: Layout,
+}
+
+/// Windows side by side need a column between them for the separator
+const SEPARATOR: usize = 1;
+
+/// The smallest a window gets: a column wide, or a line and a status line
+/// high
+fn min_size(vertical: bool) -> usize {
+    if vertical {
+        1
+    } else {
+        2
+    }
+}
+
+/// A tree of splits with windows at the leaves
+enum Node {
+    Window(usize),
+    /// Children side by side when `vertical`, else stacked, each with its
+    /// size along the split, separators not included
+    Split { vertical: bool, children: Vec<(Node, usize)> },
+}
+
+/// How the windows, by index, share the screen
+pub struct Layout {
+    root: Node,
+}
+
+impl Default for Layout {
+    fn default() -> Self {
+        Self { root: Node::Window(0) }
+    }
+}
+
+impl Layout {
+    /// Splits `window`, `extent` rows high or columns wide when `vertical`,
+    /// in two halves, `new` taking the top or left one
+    pub fn split(&mut self, window: usize, new: usize, vertical: bool, extent: usize) {
+        let room = extent.saturating_sub(if vertical { SEPARATOR } else { 0 });
+        let first = room / 2;
+        split(&mut self.root, window, (new, first), vertical, room - first);
+    }
+
+    /// Takes `window` out, its room going to a neighbour, and renumbers the
+    /// windows after it
+    pub fn close(&mut self, window: usize) {
+        remove(&mut self.root, window);
+        flatten(&mut self.root);
+        renumber(&mut self.root, window);
+    }
+
+    /// Leaves a single window on the screen, numbered 0
+    pub fn only(&mut self) {
+        self.root = Node::Window(0);
+    }
+
+    /// Where each window goes within `screen`, the sizes of the splits being
+    /// fitted to it first
+    pub fn areas(&mut self, screen: Area, count: usize) -> Vec<Area> {
+        let mut areas = vec![Area::default(); count];
+        place(&mut self.root, screen, &mut areas);
+        areas
+    }
+
+    /// The windows from top left to bottom right, as `Ctrl-W w` goes through
+    /// them
+    pub fn order(&self) -> Vec<usize> {
+        let mut windows = Vec::new();
+        leaves(&self.root, &mut windows);
+        windows
+    }
+
+    /// Makes `window` `delta` rows higher, or columns wider when `vertical`,
+    /// taking the room from the window after it or else before it
+    pub fn resize(&mut self, window: usize, vertical: bool, delta: isize) {
+        resize(&mut self.root, window, vertical, delta);
+    }
+
+    /// Gives every window of a split the same room
+    pub fn equalize(&mut self) {
+        equalize(&mut self.root);
+    }
+}
+
+fn is_window(node: &Node, window: usize) -> bool {
+    matches!(node, Node::Window(id) if *id == window)
+}
+
+fn split(node: &mut Node, window: usize, (new, first): (usize, usize), vertical: bool, rest: usize) -> bool {
+    match node {
+        Node::Window(id) if *id == window => {
+            let children = vec![(Node::Window(new), first), (Node::Window(window), rest)];
+            *node = Node::Split { vertical, children };
+            true
+        }
+        Node::Window(_) => false,
+        Node::Split { vertical: direction, children } => {
+            if *direction == vertical {
+                if let Some(index) = children.iter().position(|(child, _)| is_window(child, window)) {
+                    children[index].1 = rest;
+                    children.insert(index, (Node::Window(new), first));
+                    return true;
+                }
+            }
+            children.iter_mut().any(|(child, _)| split(child, window, (new, first), vertical, rest))
+        }
+    }
+}
+
+fn remove(node: &mut Node, window: usize) -> bool {
+    let Node::Split { vertical, children } = node else {
+        return false;
+    };
+    let Some(index) = children.iter().position(|(child, _)| is_window(child, window)) else {
+        return children.iter_mut().any(|(child, _)| remove(child, window));
+    };
+    let (_, size) = children.remove(index);
+    let separator = if *vertical { SEPARATOR } else { 0 };
+    children[index.saturating_sub(1)].1 += size + separator;
+    if children.len() == 1 {
+        *node = children.pop().unwrap().0;
+    }
+    true
+}
+
+/// Merges splits into their parent when they go the same way
+fn flatten(node: &mut Node) {
+    let Node::Split { vertical, children } = node else {
+        return;
+    };
+    let mut flat = Vec::new();
+    for (mut child, size) in children.drain(..) {
+        flatten(&mut child);
+        match child {
+            Node::Split {
+                vertical: direction,
+                children: grandchildren,
+            } if direction == *vertical => flat.extend(grandchildren),
+            child => flat.push((child, size)),
+        }
+    }
+    *children = flat;
+}
+
+fn renumber(node: &mut Node, removed: usize) {
+    match node {
+        Node::Window(id) if *id > removed => *id -= 1,
+        Node::Window(_) => (),
+        Node::Split { children, .. } => {
+            for (child, _) in children {
+                renumber(child, removed);
+            }
+        }
+    }
+}
+
+fn place(node: &mut Node, area: Area, areas: &mut [Area]) {
+    let Node::Split { vertical, children } = node else {
+        if let Node::Window(id) = node {
+            areas[*id] = area;
+        }
+        return;
+    };
+    let vertical = *vertical;
+    let (start, extent) = if vertical { (area.left, area.width) } else { (area.top, area.height) };
+    let separators = if vertical { SEPARATOR * (children.len() - 1) } else { 0 };
+    let sizes = children.iter().map(|(_, size)| *size).collect();
+    let sizes = fit(sizes, extent.saturating_sub(separators), min_size(vertical));
+    let mut start = start;
+    for ((child, size), fitted) in children.iter_mut().zip(sizes) {
+        *size = fitted;
+        let child_area = if vertical {
+            Area {
+                left: start,
+                width: fitted,
+                ..area
+            }
+        } else {
+            Area {
+                top: start,
+                height: fitted,
+                ..area
+            }
+        };
+        place(child, child_area, areas);
+        start += fitted + if vertical { SEPARATOR } else { 0 };
+    }
+}
+
+/// `sizes` scaled to add up to `total`, none smaller than `min` while there
+/// is room, the last ones making up for the rounding
+fn fit(sizes: Vec<usize>, total: usize, min: usize) -> Vec<usize> {
+    let sum: usize = sizes.iter().sum();
+    if sum == total {
+        return sizes;
+    }
+    let mut fitted: Vec<usize> = sizes.iter().map(|size| (size * total / sum.max(1)).max(min)).collect();
+    let mut excess = fitted.iter().sum::<usize>() as isize - total as isize;
+    for size in fitted.iter_mut().rev() {
+        if excess < 0 {
+            *size += excess.unsigned_abs();
+            excess = 0;
+        } else if excess > 0 {
+            let spare = size.saturating_sub(min).min(excess as usize);
+            *size -= spare;
+            excess -= spare as isize;
+        }
+    }
+    fitted
+}
+
+fn leaves(node: &Node, windows: &mut Vec<usize>) {
+    match node {
+        Node::Window(id) => windows.push(*id),
+        Node::Split { children, .. } => {
+            for (child, _) in children {
+                leaves(child, windows);
+            }
+        }
+    }
+}
+
+/// `None` when `window` isn't below `node`, else whether it was resized,
+/// which the nearest split going the right way does
+fn resize(node: &mut Node, window: usize, vertical: bool, delta: isize) -> Option<bool> {
+    let Node::Split {
+        vertical: direction,
+        children,
+    } = node
+    else {
+        return is_window(node, window).then_some(false);
+    };
+    for index in 0..children.len() {
+        match resize(&mut children[index].0, window, vertical, delta) {
+            None => continue,
+            Some(false) if *direction == vertical => {
+                let neighbour = if index + 1 < children.len() { index + 1 } else { index - 1 };
+                let min = min_size(vertical);
+                let shrink = children[index].1.saturating_sub(min) as isize;
+                let grow = children[neighbour].1.saturating_sub(min) as isize;
+                let delta = delta.clamp(-shrink, grow);
+                children[index].1 = children[index].1.saturating_add_signed(delta);
+                children[neighbour].1 = children[neighbour].1.saturating_add_signed(-delta);
+                return Some(true);
+            }
+            found => return found,
+        }
+    }
+    None
+}
+
+fn equalize(node: &mut Node) {
+    if let Node::Split { children, .. } = node {
+        let count = children.len();
+        let total: usize = children.iter().map(|(_, size)| size).sum();
+        for (index, (child, size)) in children.iter_mut().enumerate() {
+            *size = total / count + usize::from(index < total % count);
+            equalize(child);
+        }
+    }
+}